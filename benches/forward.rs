@@ -0,0 +1,41 @@
+//! CPU forward pass of the 2x and 3x networks with random weights.
+//!
+//! Run with `cargo bench`, and `cargo bench -- --save-baseline <name>` / `--baseline <name>` to
+//! compare two revisions.
+
+use candle_core::{DType, Device, Module, Tensor};
+use candle_nn::{VarBuilder, VarMap};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use real_cugan_rs::model::RealCugan;
+
+const SIZE: usize = 128;
+
+fn forward(c: &mut Criterion) {
+  let device = Device::Cpu;
+  let x = Tensor::rand(0f32, 1., (1, 3, SIZE, SIZE), &device).unwrap();
+
+  let mut group = c.benchmark_group("forward");
+  group.sample_size(10);
+
+  for scale in [2, 3] {
+    for tile_size in [None, Some(SIZE / 2)] {
+      // The variables are randomly initialised as the network asks for them
+      let varmap = VarMap::new();
+      let vb = VarBuilder::from_varmap(&varmap, DType::F32, &device);
+      let model = RealCugan::new(scale, 1.0, tile_size, true, false, vb).unwrap();
+
+      let id = match tile_size {
+        Some(tile_size) => format!("{scale}x/tile{tile_size}"),
+        None => format!("{scale}x/whole"),
+      };
+      group.bench_function(BenchmarkId::from_parameter(id), |b| {
+        b.iter(|| model.forward(&x).unwrap())
+      });
+    }
+  }
+
+  group.finish();
+}
+
+criterion_group!(benches, forward);
+criterion_main!(benches);
@@ -0,0 +1,59 @@
+use std::{env, fs, path::PathBuf};
+
+/// Generates the list of embedded models for the `embedded-models` feature.
+///
+/// Every `*.safetensors` file in `REAL_CUGAN_EMBED_DIR` (`models` by default) is compiled into
+/// the binary, under the name of the checkpoint it replaces, e.g. `pro-no-denoise-up2x`.
+fn main() {
+  println!("cargo:rerun-if-changed=build.rs");
+
+  if env::var_os("CARGO_FEATURE_EMBEDDED_MODELS").is_none() {
+    return;
+  }
+
+  println!("cargo:rerun-if-env-changed=REAL_CUGAN_EMBED_DIR");
+
+  let dir = match env::var_os("REAL_CUGAN_EMBED_DIR") {
+    Some(dir) => PathBuf::from(dir),
+    None => PathBuf::from(env::var_os("CARGO_MANIFEST_DIR").unwrap()).join("models"),
+  };
+  println!("cargo:rerun-if-changed={}", dir.display());
+
+  let mut models: Vec<_> = fs::read_dir(&dir)
+    .expect("Failed to read the directory of embedded models")
+    .filter_map(|entry| {
+      let path = entry.ok()?.path();
+      (path.extension()? == "safetensors").then_some(path)
+    })
+    .collect();
+  models.sort();
+
+  // An empty list would silently build a binary without any model
+  if models.is_empty() {
+    panic!(
+      "No safetensors model found in {}, convert the checkpoints with `cargo run --release \
+       --no-default-features --example convert_models`",
+      dir.display()
+    );
+  }
+
+  let entries: String = models
+    .iter()
+    .map(|path| {
+      println!("cargo:rerun-if-changed={}", path.display());
+
+      let name = path.file_stem().unwrap().to_str().unwrap();
+      let path = path
+        .canonicalize()
+        .expect("Failed to resolve the model path");
+      format!("  ({name:?}, include_bytes!({path:?})),\n")
+    })
+    .collect();
+
+  let out = PathBuf::from(env::var_os("OUT_DIR").unwrap()).join("embedded_models.rs");
+  fs::write(
+    out,
+    format!("pub static MODELS: &[(&str, &[u8])] = &[\n{entries}];\n"),
+  )
+  .expect("Failed to write the list of embedded models");
+}
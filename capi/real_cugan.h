@@ -0,0 +1,118 @@
+/* C API of real-cugan-rs, regenerate with `cbindgen --config cbindgen.toml --output capi/real_cugan.h` */
+
+#ifndef REAL_CUGAN_H
+#define REAL_CUGAN_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * A loaded model, opaque to C.
+ */
+typedef struct RealCuganUpscaler RealCuganUpscaler;
+
+/**
+ * How an upscaler runs, see [`real_cugan_default_options`].
+ */
+typedef struct RealCuganOptions {
+  /**
+   * 2 or 3, which must match the model.
+   */
+  uint32_t scale;
+  double alpha;
+  /**
+   * 0 runs on the whole image at once.
+   */
+  uint32_t tile_size;
+  bool no_cache;
+  bool use_cpu;
+  bool quantize;
+} RealCuganOptions;
+
+/**
+ * Pixels owned by the library, freed with [`real_cugan_image_free`].
+ */
+typedef struct RealCuganImage {
+  uint8_t *data;
+  size_t width;
+  size_t height;
+  /**
+   * Bytes per row, always `width * channels`.
+   */
+  size_t stride;
+  /**
+   * 3 for RGB8 and 4 for RGBA8.
+   */
+  uint32_t channels;
+} RealCuganImage;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Returns the options the CLI uses by default, on the CPU.
+ */
+struct RealCuganOptions real_cugan_default_options(void);
+
+/**
+ * Returns the message of the last error of the calling thread, or null if none occurred.
+ *
+ * The string stays valid until the next failing call on the same thread.
+ */
+const char *real_cugan_last_error(void);
+
+/**
+ * Loads the `.pth` or `.safetensors` model at `model_path`, returning null on failure.
+ *
+ * # Safety
+ *
+ * `model_path` must be null or a nul-terminated string, and `options` null or valid.
+ */
+struct RealCuganUpscaler *real_cugan_upscaler_new(const char *model_path,
+                                                  const struct RealCuganOptions *options);
+
+/**
+ * Frees an upscaler, doing nothing if it is null.
+ *
+ * # Safety
+ *
+ * `upscaler` must come from [`real_cugan_upscaler_new`] and not be used afterwards.
+ */
+void real_cugan_upscaler_free(struct RealCuganUpscaler *upscaler);
+
+/**
+ * Upscales an RGB8 or RGBA8 image whose rows are `stride` bytes apart into `out`, returning 0
+ * on success and -1 on failure.
+ *
+ * The colours of RGBA8 images are not premultiplied by their alpha.
+ *
+ * # Safety
+ *
+ * `data` must hold `height` rows of `stride` bytes, and `out` be null or writable.
+ */
+int real_cugan_upscale(const struct RealCuganUpscaler *upscaler,
+                       const uint8_t *data,
+                       size_t width,
+                       size_t height,
+                       size_t stride,
+                       uint32_t channels,
+                       struct RealCuganImage *out);
+
+/**
+ * Frees the pixels of an image upscaled by [`real_cugan_upscale`] and clears it.
+ *
+ * # Safety
+ *
+ * `image` must be null or filled by [`real_cugan_upscale`] and not freed yet.
+ */
+void real_cugan_image_free(struct RealCuganImage *image);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* REAL_CUGAN_H */
@@ -0,0 +1,123 @@
+/*
+ * Exercises the C API against a 2x model:
+ *
+ *   cargo build
+ *   cc capi/test.c -Icapi -Ltarget/debug -lreal_cugan_rs -o target/debug/capi-test
+ *   LD_LIBRARY_PATH=target/debug target/debug/capi-test models/pro-no-denoise-up2x.pth
+ */
+
+#include <stdio.h>
+#include <string.h>
+
+#include "real_cugan.h"
+
+#define WIDTH 13
+#define HEIGHT 9
+#define PADDING 7
+
+#define CHECK(cond)                                                   \
+  do {                                                                \
+    if (!(cond)) {                                                    \
+      const char *err = real_cugan_last_error();                      \
+      fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__, \
+              #cond);                                                 \
+      if (err) fprintf(stderr, "last error: %s\n", err);              \
+      exit(1);                                                        \
+    }                                                                 \
+  } while (0)
+
+static uint8_t pixel(size_t x, size_t y, size_t c) {
+  return (uint8_t)((x * 31 + y * 17 + c * 59) % 256);
+}
+
+/* Fills an image with rows `stride` bytes apart, the padding holding garbage. */
+static uint8_t *make_image(size_t channels, size_t stride) {
+  uint8_t *data = malloc(stride * HEIGHT);
+  memset(data, 0xAB, stride * HEIGHT);
+
+  for (size_t y = 0; y < HEIGHT; y++)
+    for (size_t x = 0; x < WIDTH; x++)
+      for (size_t c = 0; c < channels; c++)
+        data[y * stride + x * channels + c] = pixel(x, y, c);
+
+  return data;
+}
+
+static void check_upscale(RealCuganUpscaler *upscaler, uint32_t channels) {
+  size_t row_len = WIDTH * channels;
+  uint8_t *tight = make_image(channels, row_len);
+  uint8_t *padded = make_image(channels, row_len + PADDING);
+
+  RealCuganImage a, b;
+  CHECK(real_cugan_upscale(upscaler, tight, WIDTH, HEIGHT, row_len, channels, &a) == 0);
+  CHECK(real_cugan_upscale(upscaler, padded, WIDTH, HEIGHT, row_len + PADDING, channels, &b) == 0);
+
+  CHECK(a.width == WIDTH * 2 && a.height == HEIGHT * 2);
+  CHECK(a.channels == channels && a.stride == a.width * channels);
+  /* The padding of the rows is ignored */
+  CHECK(b.width == a.width && b.height == a.height);
+  CHECK(memcmp(a.data, b.data, a.stride * a.height) == 0);
+
+  real_cugan_image_free(&a);
+  real_cugan_image_free(&b);
+  CHECK(a.data == NULL);
+  real_cugan_image_free(&a);
+
+  free(tight);
+  free(padded);
+  printf("upscale %u channels: ok\n", channels);
+}
+
+int main(int argc, char **argv) {
+  if (argc < 2) {
+    fprintf(stderr, "usage: %s <2x model>\n", argv[0]);
+    return 2;
+  }
+
+  RealCuganOptions options = real_cugan_default_options();
+  CHECK(options.scale == 2 && options.tile_size == 0 && options.use_cpu);
+
+  /* Failures leave a message */
+  CHECK(real_cugan_upscaler_new(NULL, &options) == NULL);
+  CHECK(real_cugan_last_error() != NULL);
+  CHECK(real_cugan_upscaler_new("missing.pth", &options) == NULL);
+  CHECK(strstr(real_cugan_last_error(), "missing.pth") != NULL);
+
+  options.scale = 4;
+  CHECK(real_cugan_upscaler_new(argv[1], &options) == NULL);
+  options.scale = 2;
+  printf("errors: ok\n");
+
+  RealCuganUpscaler *upscaler = real_cugan_upscaler_new(argv[1], &options);
+  CHECK(upscaler != NULL);
+
+  uint8_t *data = make_image(4, WIDTH * 4);
+  RealCuganImage out;
+  CHECK(real_cugan_upscale(upscaler, data, WIDTH, HEIGHT, WIDTH * 4, 2, &out) == -1);
+  CHECK(real_cugan_upscale(upscaler, data, WIDTH, HEIGHT, WIDTH, 4, &out) == -1);
+  CHECK(real_cugan_upscale(upscaler, data, 0, HEIGHT, WIDTH * 4, 4, &out) == -1);
+  CHECK(real_cugan_upscale(NULL, data, WIDTH, HEIGHT, WIDTH * 4, 4, &out) == -1);
+  /* Sizes whose byte counts overflow */
+  CHECK(real_cugan_upscale(upscaler, data, SIZE_MAX / 2, HEIGHT, SIZE_MAX, 4, &out) == -1);
+  CHECK(strstr(real_cugan_last_error(), "too large") != NULL);
+  CHECK(real_cugan_upscale(upscaler, data, WIDTH, HEIGHT, SIZE_MAX / 2, 4, &out) == -1);
+  CHECK(strstr(real_cugan_last_error(), "too large") != NULL);
+  free(data);
+  printf("invalid images: ok\n");
+
+  check_upscale(upscaler, 3);
+  check_upscale(upscaler, 4);
+
+  /* Tiled inference gives the same size */
+  options.tile_size = 8;
+  RealCuganUpscaler *tiled = real_cugan_upscaler_new(argv[1], &options);
+  CHECK(tiled != NULL);
+  check_upscale(tiled, 4);
+
+  real_cugan_upscaler_free(tiled);
+  real_cugan_upscaler_free(upscaler);
+  real_cugan_upscaler_free(NULL);
+
+  printf("all ok\n");
+  return 0;
+}
@@ -0,0 +1,38 @@
+//! Converts the `*.pth` checkpoints of a directory (`models` by default) into the
+//! `*.safetensors` files that the `embedded-models` feature compiles into the binary:
+//!
+//!     cargo run --release --no-default-features --example convert_models [dir]
+
+use std::{env, fs, path::PathBuf};
+
+fn main() -> Result<(), candle_core::Error> {
+  let dir = env::args_os()
+    .nth(1)
+    .map(PathBuf::from)
+    .unwrap_or_else(|| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("models"));
+
+  let mut checkpoints: Vec<_> = fs::read_dir(&dir)?
+    .filter_map(|entry| {
+      let path = entry.ok()?.path();
+      (path.extension()? == "pth").then_some(path)
+    })
+    .collect();
+  checkpoints.sort();
+
+  if checkpoints.is_empty() {
+    return Err(candle_core::Error::Msg(format!("No checkpoint found in {}", dir.display())).bt());
+  }
+
+  for checkpoint in checkpoints {
+    // Git LFS pointers are not zip archives, so they fail here until `git lfs pull` is run
+    let tensors = candle_core::pickle::read_all(&checkpoint).map_err(|err| {
+      candle_core::Error::Msg(format!("Failed to read {}: {err}", checkpoint.display())).bt()
+    })?;
+
+    let output = checkpoint.with_extension("safetensors");
+    candle_core::safetensors::save(&tensors.into_iter().collect(), &output)?;
+    println!("{} -> {}", checkpoint.display(), output.display());
+  }
+
+  Ok(())
+}
@@ -0,0 +1,309 @@
+use std::{
+  fs::{self, File},
+  io::{BufReader, BufWriter},
+  path::Path,
+};
+
+use candle_core::Device;
+use image::{
+  codecs::{
+    gif::{GifDecoder, GifEncoder, Repeat},
+    png::PngDecoder,
+    webp::WebPDecoder,
+  },
+  io::Reader as ImageReader,
+  AnimationDecoder, DynamicImage, Frame, ImageFormat, RgbaImage,
+};
+
+use crate::{
+  cli::{Filter, UpscaleArgs},
+  metadata::orient,
+  model::RealCugan,
+  pipeline::{collect_stats, fit_canvas, image_to_tensor, upscale_batch},
+  plan::target_size,
+  still::Source,
+  utils::{resize_alpha, tensor_to_buffer},
+};
+
+/// Decodes every frame of an animated GIF, APNG or WebP file.
+///
+/// Returns `None` if the file is a still image. The decoders composite each frame onto the full
+/// canvas, so the offsets, blending and disposal of the source frames are already applied and
+/// every returned frame can be processed as an independent image.
+pub fn decode_animation(path: impl AsRef<Path>) -> Option<Vec<Frame>> {
+  let format = ImageReader::open(&path)
+    .expect("Failed to open image file")
+    .with_guessed_format()
+    .expect("Failed to read image file")
+    .format()?;
+
+  let reader = BufReader::new(File::open(&path).expect("Failed to open image file"));
+
+  let frames = match format {
+    ImageFormat::Gif => GifDecoder::new(reader)
+      .expect("Failed to decode image file")
+      .into_frames(),
+
+    ImageFormat::Png => {
+      let decoder = PngDecoder::new(reader).expect("Failed to decode image file");
+
+      if !decoder.is_apng() {
+        return None;
+      }
+
+      decoder.apng().into_frames()
+    }
+
+    ImageFormat::WebP => {
+      let decoder = WebPDecoder::new(reader).expect("Failed to decode image file");
+
+      if !decoder.has_animation() {
+        return None;
+      }
+
+      decoder.into_frames()
+    }
+
+    _ => return None,
+  };
+
+  let frames = frames
+    .collect_frames()
+    .expect("Failed to decode animation frames");
+
+  // A single-frame animation is handled as a still image
+  (frames.len() > 1).then_some(frames)
+}
+
+pub fn supports_animation(format: ImageFormat) -> bool {
+  matches!(
+    format,
+    ImageFormat::Gif | ImageFormat::Png | ImageFormat::WebP
+  )
+}
+
+/// Encodes full-canvas RGBA frames as an animation played `plays` times, 0 meaning forever.
+pub fn save_animation(
+  frames: Vec<Frame>,
+  path: impl AsRef<Path>,
+  format: ImageFormat,
+  lossless: bool,
+  plays: u32,
+) {
+  let (width, height) = frames[0].buffer().dimensions();
+
+  match format {
+    ImageFormat::Gif => {
+      let file = File::create(path).expect("Failed to create output image file");
+      let mut encoder = GifEncoder::new(BufWriter::new(file));
+
+      // GIF counts the repetitions after the first play, and plays once without the extension
+      let repeat = match plays {
+        0 => Some(Repeat::Infinite),
+        1 => None,
+        plays => Some(Repeat::Finite((plays - 1).try_into().unwrap_or(u16::MAX))),
+      };
+      if let Some(repeat) = repeat {
+        encoder
+          .set_repeat(repeat)
+          .expect("Failed to encode image & write to file");
+      }
+      encoder
+        .encode_frames(frames)
+        .expect("Failed to encode image & write to file");
+    }
+
+    ImageFormat::Png => {
+      if !lossless {
+        tracing::warn!("PNG images cannot be lossy, output lossless result...");
+      }
+
+      let file = File::create(path).expect("Failed to create output image file");
+      let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+      encoder.set_color(png::ColorType::Rgba);
+      encoder.set_depth(png::BitDepth::Eight);
+      encoder.set_compression(png::Compression::Fast);
+      encoder
+        .set_animated(frames.len().try_into().unwrap_or(u32::MAX), plays)
+        .expect("Failed to encode image & write to file");
+
+      let mut writer = encoder
+        .write_header()
+        .expect("Failed to encode image & write to file");
+
+      for frame in &frames {
+        writer
+          .set_frame_delay(frame_delay_ms(frame).try_into().unwrap_or(u16::MAX), 1000)
+          .and_then(|()| writer.write_image_data(frame.buffer()))
+          .expect("Failed to encode image & write to file");
+      }
+
+      writer
+        .finish()
+        .expect("Failed to encode image & write to file");
+    }
+
+    ImageFormat::WebP => {
+      let mut config = webp::WebPConfig::new().expect("Failed to initialize the WebP encoder");
+      config.lossless = lossless.into();
+      config.quality = 100.;
+
+      let mut encoder = webp::AnimEncoder::new(width, height, &config);
+      // The loop count of WebP has 16 bits
+      encoder.set_loop_count(u16::try_from(plays).unwrap_or(u16::MAX).into());
+
+      let mut timestamp = 0;
+      for frame in &frames {
+        encoder.add_frame(webp::AnimFrame::from_rgba(
+          frame.buffer(),
+          width,
+          height,
+          timestamp.try_into().unwrap_or(i32::MAX),
+        ));
+        timestamp += frame_delay_ms(frame);
+      }
+
+      let data = encoder
+        .try_encode()
+        .expect("Failed to encode image & write to file");
+
+      fs::write(path, &*data).expect("Failed to write output image file");
+    }
+
+    _ => {
+      panic!("Unsupported output animation format");
+    }
+  }
+}
+
+fn frame_delay_ms(frame: &Frame) -> u32 {
+  let (numer, denom) = frame.delay().numer_denom_ms();
+  numer / denom.max(1)
+}
+
+/// Upscales the frames of an animation and saves them as an animation of the same format,
+/// keeping the frame delays and the loop count.
+pub fn upscale_animation(
+  args: &UpscaleArgs,
+  passes: &[&RealCugan],
+  device: &Device,
+  frames: Vec<Frame>,
+  source: Source,
+  output_path: &Path,
+  output_format: ImageFormat,
+) -> Result<(), candle_core::Error> {
+  let Source {
+    mut metadata,
+    profile,
+    ..
+  } = source;
+  let profile = profile.as_ref();
+  let orientation = metadata.take_orientation();
+
+  let (width, height) = frames[0].buffer().dimensions();
+  let (width, height) = if orientation >= 5 {
+    (height, width)
+  } else {
+    (width, height)
+  };
+  let width: usize = width.try_into()?;
+  let height: usize = height.try_into()?;
+
+  let frame_num = frames.len();
+  tracing::info!(width, height, frame_num, "Animation file read");
+
+  let ((target_width, target_height), canvas) = target_size(
+    args,
+    width,
+    height,
+    passes.iter().map(|model| model.scale()).product(),
+  );
+  metadata.scale_resolution(
+    target_width as f64 / width as f64,
+    target_height as f64 / height as f64,
+  );
+
+  let mut stats = None;
+  let mut res_frames = Vec::with_capacity(frame_num);
+  let mut frames = frames.into_iter();
+
+  loop {
+    let batch: Vec<_> = frames.by_ref().take(args.batch_size).collect();
+    if batch.is_empty() {
+      break;
+    }
+
+    let mut delays = Vec::with_capacity(batch.len());
+    let mut rgbs = Vec::with_capacity(batch.len());
+    let mut alphas = Vec::with_capacity(batch.len());
+
+    for frame in batch {
+      delays.push(frame.delay());
+      let mut img = orient(DynamicImage::ImageRgba8(frame.into_buffer()), orientation);
+      if let Some(profile) = profile {
+        img = profile.to_srgb(img);
+      }
+      let (rgb, alpha) = image_to_tensor(img, true, device)?;
+      rgbs.push(rgb);
+      alphas.push(alpha);
+    }
+
+    if args.share_se_stats && stats.is_none() {
+      stats = Some(collect_stats(passes, &rgbs[0])?);
+      tracing::info!("SE statistics collected from the first frame");
+    }
+
+    let res = upscale_batch(
+      passes,
+      &rgbs,
+      stats.as_deref(),
+      target_width,
+      target_height,
+      args.filter.unwrap_or(Filter::Lanczos3),
+      args.linear_resize,
+    )?;
+    drop(rgbs);
+
+    for ((res, alpha), delay) in res.iter().zip(alphas).zip(delays) {
+      let alpha = alpha
+        .map(|alpha| {
+          let filter = args.filter.unwrap_or(Filter::Mitchell).into();
+          resize_alpha(&alpha, width, height, target_width, target_height, filter)
+        })
+        .transpose()?;
+
+      let (mut buffer, color_type) = tensor_to_buffer(res, alpha)?;
+      if let Some(profile) = profile.filter(|_| !args.strip_metadata) {
+        profile.restore(&mut buffer, color_type);
+      }
+      let buffer = fit_canvas(
+        buffer,
+        4,
+        (target_width, target_height),
+        canvas,
+        args.pad_color,
+      );
+      let buffer = RgbaImage::from_raw(canvas.0.try_into()?, canvas.1.try_into()?, buffer)
+        .expect("Failed to build the upscaled frame");
+
+      res_frames.push(Frame::from_parts(buffer, 0, 0, delay));
+
+      tracing::info!(frame = res_frames.len(), frame_num, "Frame processed");
+    }
+  }
+
+  save_animation(
+    res_frames,
+    output_path,
+    output_format,
+    args.lossless,
+    metadata.plays.unwrap_or(0),
+  );
+  if !args.strip_metadata {
+    metadata.write(output_path, output_format);
+  }
+
+  tracing::info!(path = ?output_path, "Animation saved");
+
+  Ok(())
+}
@@ -0,0 +1,239 @@
+use std::{
+  fs,
+  path::{Path, PathBuf},
+};
+
+use candle_core::Device;
+use image::ImageFormat;
+
+use crate::{
+  animation::{decode_animation, supports_animation, upscale_animation},
+  cli::{Filter, UpscaleArgs},
+  model::{CancelToken, RealCugan},
+  pipeline::upscale_batch,
+  plan::{chain_models, load_models, plan_passes, target_size, Pass},
+  setup::output_format,
+  still::{Source, Still},
+};
+
+/// Returns the files directly in `dir`, sorted by name.
+pub fn list_files(dir: &Path) -> Vec<PathBuf> {
+  let mut paths: Vec<_> = fs::read_dir(dir)
+    .expect("Failed to read the input directory")
+    .filter_map(|entry| Some(entry.ok()?.path()))
+    .filter(|path| path.is_file())
+    .collect();
+
+  paths.sort();
+  paths
+}
+
+/// Returns whether the file is an image in a supported format, logging it otherwise.
+pub fn is_image(path: &Path) -> bool {
+  let supported = ImageFormat::from_path(path).is_ok() && image::image_dimensions(path).is_ok();
+  if !supported {
+    tracing::warn!(?path, "Not a supported image, skip");
+  }
+  supported
+}
+
+/// Returns the path of the upscaled image of `path` in the output directory, which keeps its
+/// file name.
+pub fn output_path(args: &UpscaleArgs, path: &Path) -> PathBuf {
+  args
+    .output_path
+    .join(path.file_name().expect("Listed files have a name"))
+}
+
+/// The models of the last plan, kept loaded while the next images have the same plan.
+#[derive(Default)]
+pub struct LoadedModels {
+  plan: Vec<Pass>,
+  models: Vec<(Pass, RealCugan)>,
+}
+
+impl LoadedModels {
+  /// Returns the model to run for each pass of the plan, loading them unless the plan is the
+  /// loaded one, or `None` if one is missing.
+  pub fn chain(
+    &mut self,
+    plan: &[Pass],
+    args: &UpscaleArgs,
+    device: &Device,
+    cancel: Option<&CancelToken>,
+  ) -> Result<Option<Vec<&RealCugan>>, candle_core::Error> {
+    if self.plan != plan {
+      // Free the previous models first
+      self.plan.clear();
+      self.models.clear();
+
+      let Some(models) = load_models(plan, args, device, cancel)? else {
+        return Ok(None);
+      };
+      self.models = models;
+      self.plan = plan.to_vec();
+
+      tracing::info!("Network built");
+    }
+
+    Ok(Some(chain_models(plan, &self.models)))
+  }
+}
+
+/// Upscales images of the same upright size into the output directory, up to `--batch-size` of
+/// them going through the network at once.
+///
+/// Animations are upscaled on their own, `--batch-size` of their frames at once.
+///
+/// Returns `false` if a model is missing.
+pub fn upscale_group(
+  args: &UpscaleArgs,
+  device: &Device,
+  cancel: Option<&CancelToken>,
+  models: &mut LoadedModels,
+  group: Vec<(PathBuf, Source)>,
+) -> Result<bool, candle_core::Error> {
+  let (width, height) = (group[0].1.width, group[0].1.height);
+  let plan = plan_passes(args, width, height);
+
+  let Some(passes) = models.chain(&plan, args, device, cancel)? else {
+    return Ok(false);
+  };
+
+  let (target, canvas) = target_size(
+    args,
+    width,
+    height,
+    plan.iter().map(|pass| pass.scale).product(),
+  );
+
+  let mut group = group.into_iter();
+
+  loop {
+    let batch: Vec<_> = group.by_ref().take(args.batch_size).collect();
+    if batch.is_empty() {
+      break;
+    }
+
+    let mut stills = Vec::with_capacity(batch.len());
+
+    for (path, source) in batch {
+      let output_path = output_path(args, &path);
+
+      let output_format = match output_format(&output_path, args.lossless) {
+        Ok(output_format) => output_format,
+        Err(err) => {
+          tracing::error!(?path, "{err}");
+          continue;
+        }
+      };
+
+      if let Some(frames) = decode_animation(&path) {
+        if supports_animation(output_format) {
+          upscale_animation(
+            args,
+            &passes,
+            device,
+            frames,
+            source,
+            &output_path,
+            output_format,
+          )?;
+          continue;
+        }
+
+        tracing::warn!(
+          ?path,
+          "The output format does not support animation, only the first frame is used"
+        );
+      }
+
+      match Still::decode(&path, source, output_format, device)? {
+        Some(still) => stills.push((still, output_path, output_format)),
+        None => tracing::warn!(?path, "Image skipped"),
+      }
+    }
+
+    if stills.is_empty() {
+      continue;
+    }
+
+    let rgbs: Vec<_> = stills.iter().map(|(still, ..)| still.rgb.clone()).collect();
+    let res = upscale_batch(
+      &passes,
+      &rgbs,
+      None,
+      target.0,
+      target.1,
+      args.filter.unwrap_or(Filter::Lanczos3),
+      args.linear_resize,
+    )?;
+    drop(rgbs);
+
+    for ((still, output_path, output_format), res) in stills.into_iter().zip(res) {
+      still.save(args, &res, target, canvas, &output_path, output_format)?;
+    }
+  }
+
+  Ok(true)
+}
+
+/// Returns whether the output directory is the input directory, logging it if so.
+pub fn same_dirs(args: &UpscaleArgs) -> bool {
+  let input_dir = fs::canonicalize(&args.input_path).expect("Failed to read the input directory");
+  let same = fs::canonicalize(&args.output_path).ok() == Some(input_dir);
+  if same {
+    tracing::error!("The output directory must differ from the input directory");
+  }
+  same
+}
+
+/// Upscales every image of the input directory into the output directory, keeping the file
+/// names and formats.
+///
+/// Images are grouped by size, and up to `--batch-size` images of a group go through the network
+/// at once.
+pub fn upscale_dir(
+  args: &UpscaleArgs,
+  device: &Device,
+  cancel: Option<&CancelToken>,
+) -> Result<(), candle_core::Error> {
+  fs::create_dir_all(&args.output_path).expect("Failed to create the output directory");
+
+  if same_dirs(args) {
+    return Ok(());
+  }
+
+  let paths: Vec<_> = list_files(&args.input_path)
+    .into_iter()
+    .filter(|path| is_image(path))
+    .collect();
+  let image_num = paths.len();
+
+  // Groups of images with the same upright size, in the order of their first image
+  let mut groups: Vec<Vec<(PathBuf, Source)>> = vec![];
+
+  for path in paths {
+    let source = Source::read(args, &path)?;
+    let same_size =
+      |(_, other): &(PathBuf, Source)| other.width == source.width && other.height == source.height;
+
+    match groups.iter_mut().find(|group| same_size(&group[0])) {
+      Some(group) => group.push((path, source)),
+      None => groups.push(vec![(path, source)]),
+    }
+  }
+
+  tracing::info!(image_num, group_num = groups.len(), "Input directory read");
+
+  // Groups of different sizes often have the same plan, which keeps the models loaded
+  let mut models = LoadedModels::default();
+
+  for group in groups {
+    if !upscale_group(args, device, cancel, &mut models, group)? {
+      return Ok(());
+    }
+  }
+
+  Ok(())
+}
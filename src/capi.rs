@@ -0,0 +1,271 @@
+//! The C API declared in `capi/real_cugan.h`.
+//!
+//! Failing functions return null or `-1` and leave a message for [`real_cugan_last_error`]. Panics
+//! are caught rather than unwinding into the caller.
+
+use std::{
+  cell::RefCell,
+  ffi::{c_char, c_int, CStr, CString},
+  panic::{self, AssertUnwindSafe},
+  path::Path,
+  ptr, slice,
+};
+
+use candle_core::{DType, Device, Tensor};
+use candle_nn::VarBuilder;
+
+use crate::{model::RealCugan, utils::upscale_pixels};
+
+thread_local! {
+  static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+  let message = CString::new(message.replace('\0', " ")).expect("Nul bytes are replaced");
+  LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+/// Runs `f`, turning its errors and panics into the last error and `fallback`.
+fn guard<T>(fallback: T, f: impl FnOnce() -> Result<T, String>) -> T {
+  let res = panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+    Err(match payload.downcast::<String>() {
+      Ok(message) => *message,
+      Err(payload) => match payload.downcast::<&str>() {
+        Ok(message) => (*message).to_owned(),
+        Err(_) => "Unknown panic".to_owned(),
+      },
+    })
+  });
+
+  res.unwrap_or_else(|message| {
+    set_last_error(message);
+    fallback
+  })
+}
+
+/// How an upscaler runs, see [`real_cugan_default_options`].
+#[repr(C)]
+pub struct RealCuganOptions {
+  /// 2 or 3, which must match the model.
+  pub scale: u32,
+  pub alpha: f64,
+  /// 0 runs on the whole image at once.
+  pub tile_size: u32,
+  pub no_cache: bool,
+  pub use_cpu: bool,
+  pub quantize: bool,
+}
+
+/// Returns the options the CLI uses by default, on the CPU.
+#[no_mangle]
+pub extern "C" fn real_cugan_default_options() -> RealCuganOptions {
+  RealCuganOptions {
+    scale: 2,
+    alpha: 1.0,
+    tile_size: 0,
+    no_cache: false,
+    use_cpu: true,
+    quantize: false,
+  }
+}
+
+/// A loaded model, opaque to C.
+pub struct RealCuganUpscaler {
+  model: RealCugan,
+  device: Device,
+}
+
+/// Pixels owned by the library, freed with [`real_cugan_image_free`].
+#[repr(C)]
+pub struct RealCuganImage {
+  pub data: *mut u8,
+  pub width: usize,
+  pub height: usize,
+  /// Bytes per row, always `width * channels`.
+  pub stride: usize,
+  /// 3 for RGB8 and 4 for RGBA8.
+  pub channels: u32,
+}
+
+/// Returns the message of the last error of the calling thread, or null if none occurred.
+///
+/// The string stays valid until the next failing call on the same thread.
+#[no_mangle]
+pub extern "C" fn real_cugan_last_error() -> *const c_char {
+  LAST_ERROR.with(|last| {
+    last
+      .borrow()
+      .as_ref()
+      .map_or(ptr::null(), |message| message.as_ptr())
+  })
+}
+
+fn load_model(path: &Path, options: &RealCuganOptions) -> Result<RealCuganUpscaler, String> {
+  if !matches!(options.scale, 2 | 3) {
+    return Err(format!("unsupported upscale ratio {}", options.scale));
+  }
+
+  let build = || -> Result<RealCuganUpscaler, candle_core::Error> {
+    let device = if options.use_cpu {
+      Device::Cpu
+    } else {
+      Device::new_cuda(0)?
+    };
+
+    let vb = if path.extension().is_some_and(|ext| ext == "safetensors") {
+      let tensors = candle_core::safetensors::load(path, &device)?;
+      VarBuilder::from_tensors(tensors, DType::F32, &device)
+    } else {
+      VarBuilder::from_pth(path, DType::F32, &device)?
+    };
+
+    let tile_size = (options.tile_size > 0).then_some(options.tile_size as usize);
+    let model = RealCugan::new(
+      options.scale as usize,
+      options.alpha,
+      tile_size,
+      !options.no_cache,
+      options.quantize,
+      vb,
+    )?;
+
+    Ok(RealCuganUpscaler { model, device })
+  };
+
+  build().map_err(|err| format!("Failed to load the model {path:?}: {err}"))
+}
+
+/// Loads the `.pth` or `.safetensors` model at `model_path`, returning null on failure.
+///
+/// # Safety
+///
+/// `model_path` must be null or a nul-terminated string, and `options` null or valid.
+#[no_mangle]
+pub unsafe extern "C" fn real_cugan_upscaler_new(
+  model_path: *const c_char,
+  options: *const RealCuganOptions,
+) -> *mut RealCuganUpscaler {
+  guard(ptr::null_mut(), || {
+    if model_path.is_null() {
+      return Err("The model path is null".to_owned());
+    }
+    let model_path = unsafe { CStr::from_ptr(model_path) }
+      .to_str()
+      .map_err(|_| "The model path is not valid UTF-8".to_owned())?;
+
+    let default = real_cugan_default_options();
+    let options = unsafe { options.as_ref() }.unwrap_or(&default);
+
+    let upscaler = load_model(Path::new(model_path), options)?;
+    Ok(Box::into_raw(Box::new(upscaler)))
+  })
+}
+
+/// Frees an upscaler, doing nothing if it is null.
+///
+/// # Safety
+///
+/// `upscaler` must come from [`real_cugan_upscaler_new`] and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn real_cugan_upscaler_free(upscaler: *mut RealCuganUpscaler) {
+  if !upscaler.is_null() {
+    drop(unsafe { Box::from_raw(upscaler) });
+  }
+}
+
+/// Upscales tightly packed pixels, keeping the alpha channel if any.
+fn upscale(
+  upscaler: &RealCuganUpscaler,
+  pixels: Vec<u8>,
+  width: usize,
+  height: usize,
+  channels: usize,
+) -> Result<RealCuganImage, candle_core::Error> {
+  let data = Tensor::from_vec(pixels, (height, width, channels), &upscaler.device)?;
+  let (buffer, width, height) = upscale_pixels(&upscaler.model, &data)?;
+  let data = Box::into_raw(buffer.into_boxed_slice()) as *mut u8;
+
+  Ok(RealCuganImage {
+    data,
+    width,
+    height,
+    stride: width * channels,
+    channels: channels as u32,
+  })
+}
+
+/// Upscales an RGB8 or RGBA8 image whose rows are `stride` bytes apart into `out`, returning 0
+/// on success and -1 on failure.
+///
+/// The colours of RGBA8 images are not premultiplied by their alpha.
+///
+/// # Safety
+///
+/// `data` must hold `height` rows of `stride` bytes, and `out` be null or writable.
+#[no_mangle]
+pub unsafe extern "C" fn real_cugan_upscale(
+  upscaler: *const RealCuganUpscaler,
+  data: *const u8,
+  width: usize,
+  height: usize,
+  stride: usize,
+  channels: u32,
+  out: *mut RealCuganImage,
+) -> c_int {
+  guard(-1, || {
+    let Some(upscaler) = (unsafe { upscaler.as_ref() }) else {
+      return Err("The upscaler is null".to_owned());
+    };
+    if data.is_null() || out.is_null() {
+      return Err("The input or output image is null".to_owned());
+    }
+    if !matches!(channels, 3 | 4) {
+      return Err(format!("unsupported channel count {channels}"));
+    }
+    if width == 0 || height == 0 {
+      return Err("The image is empty".to_owned());
+    }
+
+    let too_large = || format!("The image of {width}x{height} pixels is too large");
+
+    let row_len = width.checked_mul(channels as usize).ok_or_else(too_large)?;
+    if stride < row_len {
+      return Err(format!("The stride {stride} is shorter than a row"));
+    }
+
+    let len = stride
+      .checked_mul(height - 1)
+      .and_then(|len| len.checked_add(row_len))
+      .filter(|&len| isize::try_from(len).is_ok())
+      .ok_or_else(too_large)?;
+    let input = unsafe { slice::from_raw_parts(data, len) };
+    let pixels: Vec<u8> = (0..height)
+      .flat_map(|row| &input[row * stride..row * stride + row_len])
+      .copied()
+      .collect();
+
+    let image = upscale(upscaler, pixels, width, height, channels as usize)
+      .map_err(|err| format!("Failed to upscale the image: {err}"))?;
+    unsafe { out.write(image) };
+    Ok(0)
+  })
+}
+
+/// Frees the pixels of an image upscaled by [`real_cugan_upscale`] and clears it.
+///
+/// # Safety
+///
+/// `image` must be null or filled by [`real_cugan_upscale`] and not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn real_cugan_image_free(image: *mut RealCuganImage) {
+  let Some(image) = (unsafe { image.as_mut() }) else {
+    return;
+  };
+
+  if !image.data.is_null() {
+    let len = image.stride * image.height;
+    drop(unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(image.data, len)) });
+  }
+
+  image.data = ptr::null_mut();
+}
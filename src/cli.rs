@@ -1,16 +1,288 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, time::Duration};
 
-use clap::Parser;
+use clap::{Args, Parser, Subcommand, ValueEnum};
 
 #[derive(Parser)]
 #[command(version, author)]
 #[command(about = "A Rust port of Real-CUGAN", long_about = None)]
+#[command(
+  args_conflicts_with_subcommands = true,
+  subcommand_negates_reqs = true,
+  arg_required_else_help = true
+)]
 pub struct Cli {
+  #[command(subcommand)]
+  pub command: Option<Command>,
+
+  #[command(flatten)]
+  pub upscale: Option<UpscaleArgs>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+  /// List the installed models and check whether they are valid
+  ListModels(ListModelsArgs),
+  /// Serve upscaling requests over HTTP, keeping the models loaded between requests
+  Serve(ServeArgs),
+  /// Upscale the JSON jobs read line by line from stdin, keeping the models loaded between jobs
+  Worker(WorkerArgs),
+  /// Upscale the images written into the input directory into the output directory as they come
+  Watch(WatchArgs),
+  /// Compare the output of a model with a reference output of the PyTorch implementation
+  Verify(VerifyArgs),
+  /// Compute quality metrics of an image against a reference or against the image it was upscaled from
+  Compare(CompareArgs),
+}
+
+#[derive(Args)]
+pub struct ListModelsArgs {
+  #[arg(long, help = "Look for models in this directory first")]
+  #[arg(value_name = "DIR")]
+  pub model_dir: Option<PathBuf>,
+
+  #[arg(
+    long,
+    help = "Check the SHA-256 of each model against the known checksums"
+  )]
+  pub checksum: bool,
+
+  #[arg(
+    long,
+    help = "Check the SHA-256 against a `sha256sum` style manifest instead, implies `--checksum`"
+  )]
+  #[arg(value_name = "FILE")]
+  pub manifest: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct ServeArgs {
+  #[arg(long, help = "Address to listen on")]
+  #[arg(value_name = "ADDR", default_value = "127.0.0.1:8080")]
+  pub listen: String,
+
+  #[arg(long, help = "Number of requests to upscale at the same time")]
+  #[arg(value_name = "NUM", default_value = "1", value_parser = parse_positive)]
+  pub workers: usize,
+
+  #[arg(
+    long,
+    help = "Number of requests waiting for a worker before new ones are refused"
+  )]
+  #[arg(value_name = "NUM", default_value = "16")]
+  pub queue_size: usize,
+
+  #[arg(long, help = "Largest upload in MiB, larger ones being refused")]
+  #[arg(value_name = "MIB", default_value = "64", value_parser = parse_positive)]
+  pub max_upload: usize,
+
+  #[arg(
+    long,
+    value_delimiter = ',',
+    help = "Models to load on startup, e.g. `2,3:-1` for 2x and conservative 3x"
+  )]
+  #[arg(value_name = "PASSES", value_parser = parse_pass)]
+  pub preload: Vec<PassSpec>,
+
+  #[arg(short, long, help = "Tile size of the requests that do not give one")]
+  #[arg(value_name = "TILE")]
+  pub tile_size: Option<usize>,
+
+  #[arg(
+    short,
+    long,
+    help = "Alpha of the requests that do not give one, see the documentation of the upscale options"
+  )]
+  #[arg(value_name = "ALPHA", default_value = "1.0")]
+  pub alpha: f64,
+
+  #[command(flatten)]
+  pub models: ModelArgs,
+}
+
+#[derive(Args)]
+pub struct WorkerArgs {
+  #[command(flatten)]
+  pub models: ModelArgs,
+}
+
+#[derive(Args)]
+pub struct WatchArgs {
+  #[command(flatten)]
+  pub upscale: UpscaleArgs,
+
+  #[arg(
+    long,
+    help = "Seconds between two scans of the input directory, files are upscaled once unchanged between two scans"
+  )]
+  #[arg(value_name = "SECONDS", default_value = "2", value_parser = parse_seconds)]
+  pub interval: Duration,
+
+  #[arg(
+    long,
+    help = "File recording the upscaled images [default: `.real-cugan-rs-watch.jsonl` in the output directory]"
+  )]
+  #[arg(value_name = "FILE")]
+  pub record: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct VerifyArgs {
   #[arg(short, long, help = "Input image path")]
   #[arg(value_name = "INPUT")]
   pub input_path: PathBuf,
 
-  #[arg(short, long, help = "Output image path")]
+  #[arg(
+    short,
+    long,
+    help = "Reference output of the PyTorch implementation, a `.npy` array or an image such as a 16-bit PNG"
+  )]
+  #[arg(value_name = "REFERENCE")]
+  pub reference: PathBuf,
+
+  #[arg(
+    long,
+    help = "Value of white in a `.npy` reference [default: 255 for uint8, 1.0 otherwise]"
+  )]
+  #[arg(value_name = "MAX")]
+  pub npy_max: Option<f64>,
+
+  #[arg(
+    long,
+    help = "Write a heatmap of the absolute difference, white at the maximum difference or 1 if smaller"
+  )]
+  #[arg(value_name = "HEATMAP")]
+  pub heatmap: Option<PathBuf>,
+
+  #[arg(
+    long,
+    help = "Exit with an error if the maximum absolute difference is above this, in [0, 255] units"
+  )]
+  #[arg(value_name = "DIFF")]
+  pub max_diff: Option<f64>,
+
+  #[arg(short, long, help = "Upscale ratio (2/3)")]
+  #[arg(value_name = "SCALE", default_value = "2")]
+  pub scale: u8,
+
+  #[arg(
+    short,
+    long,
+    help = "Denoise level (-1/0/3), -1 for conservative model"
+  )]
+  #[arg(value_name = "DENOISE", default_value = "0")]
+  pub denoise_level: String,
+
+  #[arg(short, long, help = "Please check the documentation for this option")]
+  #[arg(value_name = "ALPHA", default_value = "1.0")]
+  pub alpha: f64,
+
+  #[arg(short, long, help = "Tile size, smaller value may reduce memory usage")]
+  #[arg(value_name = "TILE")]
+  pub tile_size: Option<usize>,
+
+  #[arg(
+    long,
+    conflicts_with = "model_dir",
+    help = "Use this model file instead of searching for one, it must match `--scale`"
+  )]
+  #[arg(value_name = "MODEL")]
+  pub model: Option<PathBuf>,
+
+  #[arg(long, help = "Look for models in this directory first")]
+  #[arg(value_name = "DIR")]
+  pub model_dir: Option<PathBuf>,
+
+  #[arg(
+    long,
+    help = "Disable cache, which increases runtime but reduce memory usage"
+  )]
+  pub no_cache: bool,
+
+  #[arg(short = 'C', long, help = "Use CPU instead of GPU for inference")]
+  pub use_cpu: bool,
+}
+
+#[derive(Args)]
+pub struct CompareArgs {
+  #[arg(short, long, help = "Image to rate, e.g. an upscaled output")]
+  #[arg(value_name = "IMAGE")]
+  pub image: PathBuf,
+
+  #[arg(
+    short,
+    long,
+    required_unless_present = "source",
+    help = "Image of the same size to compare with"
+  )]
+  #[arg(value_name = "REFERENCE")]
+  pub reference: Option<PathBuf>,
+
+  #[arg(
+    long,
+    help = "Image the rated one was upscaled from, compared with the rated one downscaled to its size"
+  )]
+  #[arg(value_name = "SOURCE")]
+  pub source: Option<PathBuf>,
+
+  #[arg(
+    long,
+    help = "Also compute the MS-SSIM, which needs sides of at least 176 pixels"
+  )]
+  pub ms_ssim: bool,
+
+  #[arg(long, help = "Downscaling filter of `--source`")]
+  #[arg(value_name = "FILTER", default_value = "lanczos3")]
+  pub filter: Filter,
+
+  #[arg(short = 'C', long, help = "Use CPU instead of GPU for the metrics")]
+  pub use_cpu: bool,
+}
+
+/// How the long-running modes load and run their models.
+#[derive(Args)]
+pub struct ModelArgs {
+  #[arg(long, help = "Look for models in this directory first")]
+  #[arg(value_name = "DIR")]
+  pub model_dir: Option<PathBuf>,
+
+  #[arg(
+    long,
+    help = "Number of models kept loaded, the least recently used one being dropped first"
+  )]
+  #[arg(value_name = "NUM", default_value = "4", value_parser = parse_positive)]
+  pub max_models: usize,
+
+  #[arg(
+    long,
+    help = "Disable cache, which increases runtime but reduce memory usage"
+  )]
+  pub no_cache: bool,
+
+  #[arg(short = 'C', long, help = "Use CPU instead of GPU for inference")]
+  pub use_cpu: bool,
+
+  #[arg(
+    long,
+    help = "Round the weights of the 3x3 convolutions to int8, one scale per output channel"
+  )]
+  pub quantize: bool,
+}
+
+#[derive(Args)]
+pub struct UpscaleArgs {
+  #[arg(
+    short,
+    long,
+    help = "Input image path, or a directory to upscale every image in it"
+  )]
+  #[arg(value_name = "INPUT")]
+  pub input_path: PathBuf,
+
+  #[arg(
+    short,
+    long,
+    help = "Output image path, or a directory if the input is a directory"
+  )]
   #[arg(value_name = "OUTPUT")]
   pub output_path: PathBuf,
 
@@ -18,6 +290,22 @@ pub struct Cli {
   #[arg(value_name = "SCALE", default_value = "2")]
   pub scale: u8,
 
+  #[arg(
+    long,
+    conflicts_with = "scale",
+    help = "Choose the smallest chain of 2x/3x passes that reaches the target width/height"
+  )]
+  pub auto_scale: bool,
+
+  #[arg(
+    long,
+    value_delimiter = ',',
+    conflicts_with_all = ["scale", "auto_scale"],
+    help = "Chain of network passes, e.g. `3,2:-1` for 3x then 2x with the conservative model"
+  )]
+  #[arg(value_name = "PASSES", value_parser = parse_pass)]
+  pub passes: Vec<PassSpec>,
+
   #[arg(
     short,
     long,
@@ -26,6 +314,26 @@ pub struct Cli {
   #[arg(value_name = "DENOISE", default_value = "0")]
   pub denoise_level: String,
 
+  #[arg(
+    long,
+    conflicts_with_all = ["denoise_level", "model"],
+    help = "Blend the no-denoise (0.0) and denoise3x (1.0) models instead of picking a denoise level"
+  )]
+  #[arg(value_name = "STRENGTH", value_parser = parse_strength)]
+  pub denoise_strength: Option<f64>,
+
+  #[arg(
+    long,
+    conflicts_with_all = ["passes", "auto_scale", "model_dir"],
+    help = "Use this model file instead of searching for one, it must match `--scale`"
+  )]
+  #[arg(value_name = "MODEL")]
+  pub model: Option<PathBuf>,
+
+  #[arg(long, help = "Look for models in this directory first")]
+  #[arg(value_name = "DIR")]
+  pub model_dir: Option<PathBuf>,
+
   #[arg(short, long, help = "Output lossless encoded image")]
   pub lossless: bool,
 
@@ -33,6 +341,13 @@ pub struct Cli {
   #[arg(value_name = "TILE")]
   pub tile_size: Option<usize>,
 
+  #[arg(
+    long,
+    help = "Number of same-size images or animation frames to run through the network at once"
+  )]
+  #[arg(value_name = "SIZE", default_value = "1", value_parser = parse_positive)]
+  pub batch_size: usize,
+
   #[arg(short = 'W', long, help = "After Real-CUGAN, resample to target width")]
   #[arg(value_name = "WIDTH")]
   pub width: Option<usize>,
@@ -45,6 +360,27 @@ pub struct Cli {
   #[arg(value_name = "HEIGHT")]
   pub height: Option<usize>,
 
+  #[arg(
+    long,
+    help = "Resampling filter [default: lanczos3, mitchell for alpha]"
+  )]
+  #[arg(value_name = "FILTER")]
+  pub filter: Option<Filter>,
+
+  #[arg(
+    long,
+    help = "How to fit the image when both width and height are given"
+  )]
+  #[arg(value_name = "FIT", default_value = "stretch")]
+  pub fit: Fit,
+
+  #[arg(
+    long,
+    help = "Padding colour of `--fit contain`, in RRGGBB or RRGGBBAA"
+  )]
+  #[arg(value_name = "COLOR", default_value = "000000", value_parser = parse_color)]
+  pub pad_color: [u8; 4],
+
   #[arg(
     long,
     help = "Disable cache, which increases runtime but reduce memory usage"
@@ -54,7 +390,122 @@ pub struct Cli {
   #[arg(short = 'C', long, help = "Use CPU instead of GPU for inference")]
   pub use_cpu: bool,
 
+  #[arg(
+    long,
+    help = "Stop the inference if it is still running after this many seconds"
+  )]
+  #[arg(value_name = "SECONDS", value_parser = parse_seconds)]
+  pub timeout: Option<Duration>,
+
+  #[arg(
+    long,
+    help = "Round the weights of the 3x3 convolutions to int8, one scale per output channel"
+  )]
+  pub quantize: bool,
+
+  #[arg(
+    long,
+    requires = "quantize",
+    help = "Also run the F32 network and report the PSNR of the int8 result against it"
+  )]
+  pub quantize_report: bool,
+
   #[arg(short, long, help = "Please check the documentation for this option")]
   #[arg(value_name = "ALPHA", default_value = "1.0")]
   pub alpha: f64,
+
+  #[arg(
+    long,
+    help = "Reuse the SE statistics of the first frame for all frames of an animation"
+  )]
+  pub share_se_stats: bool,
+
+  #[arg(
+    long,
+    help = "Convert the input from its embedded ICC profile to sRGB before inference, and back afterwards"
+  )]
+  pub color_manage: bool,
+
+  #[arg(long, help = "Resample to the target size in linear light")]
+  pub linear_resize: bool,
+
+  #[arg(
+    long,
+    help = "Do not copy the ICC profile, EXIF and XMP metadata into the output"
+  )]
+  pub strip_metadata: bool,
+}
+
+/// A pass given to `--passes`, using `--denoise-level` if the denoise level is omitted.
+#[derive(Clone)]
+pub struct PassSpec {
+  pub scale: u8,
+  pub denoise_level: Option<String>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Filter {
+  Point,
+  Triangle,
+  CatmullRom,
+  Mitchell,
+  Lanczos3,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Fit {
+  Stretch,
+  Contain,
+  Cover,
+  Max,
+}
+
+fn parse_color(s: &str) -> Result<[u8; 4], String> {
+  let hex = s.strip_prefix('#').unwrap_or(s);
+  if !matches!(hex.len(), 6 | 8) || !hex.is_ascii() {
+    return Err("expected RRGGBB or RRGGBBAA".to_owned());
+  }
+
+  let mut color = [255; 4];
+  for (i, c) in color.iter_mut().take(hex.len() / 2).enumerate() {
+    *c = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|err| err.to_string())?;
+  }
+
+  Ok(color)
+}
+
+fn parse_strength(s: &str) -> Result<f64, String> {
+  match s.parse() {
+    Ok(strength) if (0.0..=1.0).contains(&strength) => Ok(strength),
+    _ => Err("expected a number between 0.0 and 1.0".to_owned()),
+  }
+}
+
+fn parse_seconds(s: &str) -> Result<Duration, String> {
+  match s.parse() {
+    Ok(secs) if secs > 0. => Duration::try_from_secs_f64(secs).map_err(|err| err.to_string()),
+    _ => Err("expected a positive number of seconds".to_owned()),
+  }
+}
+
+fn parse_positive(s: &str) -> Result<usize, String> {
+  match s.parse() {
+    Ok(size) if size > 0 => Ok(size),
+    _ => Err("expected a positive integer".to_owned()),
+  }
+}
+
+fn parse_pass(s: &str) -> Result<PassSpec, String> {
+  let (scale, denoise_level) = match s.split_once(':') {
+    Some((scale, denoise_level)) => (scale, Some(denoise_level.to_owned())),
+    None => (s, None),
+  };
+
+  match scale.parse() {
+    Ok(scale @ (2 | 3)) => Ok(PassSpec {
+      scale,
+      denoise_level,
+    }),
+    _ => Err("the scale of each pass must be 2 or 3".to_owned()),
+  }
 }
@@ -0,0 +1,99 @@
+use image::{ColorType, DynamicImage};
+use qcms::{DataType, Intent, Profile, Transform};
+
+/// The embedded RGB colour profile of the input, used to run the network on sRGB values.
+pub struct SourceProfile {
+  profile: Box<Profile>,
+  srgb: Box<Profile>,
+}
+
+impl SourceProfile {
+  /// Returns `None` if the profile is invalid, not an RGB profile, or already sRGB.
+  pub fn new(icc: &[u8]) -> Option<Self> {
+    // colour space signature in the profile header
+    if icc.get(16..20) != Some(b"RGB ") {
+      tracing::warn!("Only RGB colour profiles are supported, skip colour management");
+      return None;
+    }
+
+    let Some(profile) = Profile::new_from_slice(icc, false) else {
+      tracing::warn!("Failed to parse the ICC profile, skip colour management");
+      return None;
+    };
+
+    if profile.is_sRGB() {
+      tracing::info!("The input is already sRGB");
+      return None;
+    }
+
+    Some(Self {
+      profile,
+      srgb: Profile::new_sRGB(),
+    })
+  }
+
+  fn transform(&self, data: &mut [u8], ty: DataType, to_srgb: bool) {
+    let (input, output) = if to_srgb {
+      (&self.profile, &self.srgb)
+    } else {
+      (&self.srgb, &self.profile)
+    };
+
+    match Transform::new(input, output, ty, Intent::Perceptual) {
+      Some(transform) => transform.apply(data),
+      None => tracing::warn!(to_srgb, "Failed to build the colour transform, skipped"),
+    }
+  }
+
+  /// Converts the image into sRGB, keeping the alpha channel if there is one.
+  pub fn to_srgb(&self, img: DynamicImage) -> DynamicImage {
+    if img.color().has_alpha() {
+      let mut img = img.into_rgba8();
+      self.transform(&mut img, DataType::RGBA8, true);
+      DynamicImage::ImageRgba8(img)
+    } else {
+      let mut img = img.into_rgb8();
+      self.transform(&mut img, DataType::RGB8, true);
+      DynamicImage::ImageRgb8(img)
+    }
+  }
+
+  /// Converts raw sRGB pixels back into the source profile.
+  pub fn restore(&self, buffer: &mut [u8], color_type: ColorType) {
+    match color_type {
+      ColorType::Rgb8 => self.transform(buffer, DataType::RGB8, false),
+      ColorType::Rgba8 => self.transform(buffer, DataType::RGBA8, false),
+      _ => unreachable!("the output buffer is either RGB8 or RGBA8"),
+    }
+  }
+}
+
+fn srgb_to_linear(v: f32) -> f32 {
+  if v <= 0.04045 {
+    v / 12.92
+  } else {
+    ((v + 0.055) / 1.055).powf(2.4)
+  }
+}
+
+fn linear_to_srgb(v: f32) -> f32 {
+  if v <= 0.0031308 {
+    v * 12.92
+  } else {
+    1.055 * v.powf(1. / 2.4) - 0.055
+  }
+}
+
+/// Converts `[0, 255]` sRGB values into `[0, 1]` linear light in place.
+pub fn decode_gamma(data: &mut [f32]) {
+  for v in data {
+    *v = srgb_to_linear((*v / 255.).clamp(0., 1.));
+  }
+}
+
+/// Converts `[0, 1]` linear light back into `[0, 255]` sRGB values in place.
+pub fn encode_gamma(data: &mut [f32]) {
+  for v in data {
+    *v = (linear_to_srgb(v.clamp(0., 1.)) * 255.).round();
+  }
+}
@@ -0,0 +1,123 @@
+use std::path::Path;
+
+use candle_core::{Device, Tensor};
+use image::io::Reader as ImageReader;
+
+use crate::{
+  cli::CompareArgs,
+  metrics::{fits_window, ms_ssim, psnr, ssim, MS_SSIM_MIN_SIZE},
+  pipeline::{image_to_tensor, resample},
+};
+
+/// Reads the colour channels of an image as a `(height, width, 3)` tensor.
+fn read_image(path: &Path, device: &Device) -> Result<Tensor, candle_core::Error> {
+  let img = ImageReader::open(path)
+    .expect("Failed to open image file")
+    .decode()
+    .expect("Failed to decode image file");
+
+  let (rgb, _) = image_to_tensor(img, false, device)?;
+  Ok(rgb)
+}
+
+/// Logs the metrics of `image` against `reference`, which have the same size.
+fn report(
+  image: &Tensor,
+  reference: &Tensor,
+  ms: bool,
+  message: &str,
+) -> Result<(), candle_core::Error> {
+  let (height, width, _) = image.dims3()?;
+
+  let ssim = if fits_window(width, height) {
+    Some(ssim(image, reference)?)
+  } else {
+    tracing::warn!(width, height, "The images are too small for the SSIM");
+    None
+  };
+
+  let ms_ssim = if !ms {
+    None
+  } else if width.min(height) >= MS_SSIM_MIN_SIZE {
+    Some(ms_ssim(image, reference)?)
+  } else {
+    tracing::warn!(width, height, "The images are too small for the MS-SSIM");
+    None
+  };
+
+  tracing::info!(
+    psnr = format!("{:.2} dB", psnr(image, reference)?),
+    ssim = ssim.map(|ssim| format!("{ssim:.4}")),
+    ms_ssim = ms_ssim.map(|ms_ssim| format!("{ms_ssim:.4}")),
+    "{message}",
+  );
+
+  Ok(())
+}
+
+/// Compares an image with a reference of the same size, or with the image it was upscaled from
+/// once downscaled to its size.
+pub fn compare(args: CompareArgs) -> Result<(), candle_core::Error> {
+  let device = if args.use_cpu {
+    Device::Cpu
+  } else {
+    Device::new_cuda(0)?
+  };
+
+  tracing::info!(?device, "Setup device");
+
+  let image = read_image(&args.image, &device)?;
+  let (height, width, _) = image.dims3()?;
+
+  if let Some(path) = &args.reference {
+    let reference = read_image(path, &device)?;
+
+    if reference.dims() != image.dims() {
+      tracing::error!(
+        image = ?image.dims(),
+        reference = ?reference.dims(),
+        "The reference does not have the size of the image"
+      );
+      return Ok(());
+    }
+
+    report(
+      &image,
+      &reference,
+      args.ms_ssim,
+      "Compared with the reference",
+    )?;
+  }
+
+  if let Some(path) = &args.source {
+    let source = read_image(path, &device)?;
+    let (source_height, source_width, _) = source.dims3()?;
+
+    if source_width > width || source_height > height {
+      tracing::error!(
+        image = ?(width, height),
+        source = ?(source_width, source_height),
+        "The source is larger than the image"
+      );
+      return Ok(());
+    }
+
+    // An upscaled image that keeps the content of its source downscales back into it
+    let roundtrip = resample(
+      image.clone(),
+      source_width,
+      source_height,
+      args.filter,
+      false,
+    )?;
+
+    report(
+      &roundtrip,
+      &source,
+      args.ms_ssim,
+      "Compared with the source once downscaled",
+    )?;
+  }
+
+  Ok(())
+}
@@ -0,0 +1,13 @@
+//! Models compiled into the binary by the `embedded-models` feature, see `build.rs`.
+
+include!(concat!(env!("OUT_DIR"), "/embedded_models.rs"));
+
+/// Returns the embedded safetensors standing in for the checkpoint with the given file name.
+pub fn find_model(model_name: &str) -> Option<&'static [u8]> {
+  let stem = model_name.strip_suffix(".pth").unwrap_or(model_name);
+
+  MODELS
+    .iter()
+    .find(|(name, _)| *name == stem)
+    .map(|(_, data)| *data)
+}
@@ -0,0 +1,10 @@
+//! The Real-CUGAN networks, exposed as a library for the benchmarks and, through [`capi`], to C.
+//! The `python` and `wasm` features also build a Python module and a WebAssembly entry point.
+
+pub mod capi;
+pub mod model;
+#[cfg(feature = "python")]
+mod python;
+pub mod utils;
+#[cfg(feature = "wasm")]
+mod wasm;
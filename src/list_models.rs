@@ -0,0 +1,215 @@
+use std::{
+  collections::HashMap,
+  fs,
+  path::{Path, PathBuf},
+};
+
+use candle_core::Device;
+use sha2::{Digest, Sha256};
+
+use crate::{
+  cli::ListModelsArgs,
+  locate::{model_file_name, search_dirs, ModelSource},
+  model::RealCugan,
+};
+
+const DENOISE_LEVELS: [&str; 3] = ["-1", "0", "3"];
+
+/// SHA-256 of the released pro models.
+const MANIFEST: [(&str, &str); 6] = [
+  (
+    "pro-conservative-up2x.pth",
+    "b8ae5225d2d515aa3c33ef1318aadc532a42ea5ed8d564471b5a5b586783e964",
+  ),
+  (
+    "pro-conservative-up3x.pth",
+    "a9f3c783a04b15c793b95e332bfdac524cfa30ba186cb829c1290593e28ad9e7",
+  ),
+  (
+    "pro-denoise3x-up2x.pth",
+    "e80ca8fc7c261e3dc8f4c0ce0656ac5501d71a476543071615c43392dbeb4c0d",
+  ),
+  (
+    "pro-denoise3x-up3x.pth",
+    "4ddd14e2430db0d75d186c6dda934db34929c50da8a88a0c6f4accb871fe4b70",
+  ),
+  (
+    "pro-no-denoise-up2x.pth",
+    "ccce1f535d94c50ce38e268a53687bc7e68ef7215e3c5e6b3bfd1bfc1dacf0fa",
+  ),
+  (
+    "pro-no-denoise-up3x.pth",
+    "c14d693a6d3316b8a3eba362e7576f178aea3407e1d89ca0bcb34e1c61269b0f",
+  ),
+];
+
+struct ModelName<'a> {
+  family: &'a str,
+  denoise_level: String,
+  scale: usize,
+}
+
+/// Parses names like `pro-denoise3x-up2x.pth`.
+fn parse_name(name: &str) -> Option<ModelName<'_>> {
+  let stem = name.strip_suffix(".pth")?;
+  let (family, rest) = stem.split_once('-')?;
+  let (denoise, scale) = rest.rsplit_once("-up")?;
+
+  let denoise_level = match denoise {
+    "conservative" => "-1".to_owned(),
+    "no-denoise" => "0".to_owned(),
+    d => d.strip_prefix("denoise")?.strip_suffix('x')?.to_owned(),
+  };
+
+  Some(ModelName {
+    family,
+    denoise_level,
+    scale: scale.strip_suffix('x')?.parse().ok()?,
+  })
+}
+
+/// Builds the network from the weights, which checks the name and shape of every tensor the
+/// network needs.
+fn validate(source: &ModelSource, scale: usize) -> Result<(), candle_core::Error> {
+  let vb = source.var_builder(&Device::Cpu)?;
+  RealCugan::new(scale, 1.0, None, false, false, vb).map(drop)
+}
+
+fn read_manifest(path: &Path) -> HashMap<String, String> {
+  fs::read_to_string(path)
+    .expect("Failed to read the manifest")
+    .lines()
+    .filter_map(|line| {
+      let (hash, name) = line.split_once(char::is_whitespace)?;
+      // `sha256sum` marks binary mode with a leading `*`
+      let name = name.trim_start().trim_start_matches('*');
+      let name = Path::new(name).file_name()?.to_str()?;
+      Some((name.to_owned(), hash.to_ascii_lowercase()))
+    })
+    .collect()
+}
+
+/// Returns the checkpoints in the given directories, in the order they are searched.
+fn scan_dirs(dirs: &[PathBuf]) -> Vec<(String, ModelSource)> {
+  let mut found = vec![];
+
+  for dir in dirs {
+    let Ok(entries) = fs::read_dir(dir) else {
+      continue;
+    };
+
+    let mut names: Vec<_> = entries
+      .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+      .filter(|name| name.ends_with(".pth"))
+      .collect();
+    names.sort();
+
+    found.extend(names.into_iter().map(|name| {
+      let source = ModelSource::File(dir.join(&name));
+      (name, source)
+    }));
+  }
+
+  found
+}
+
+pub fn list_models(args: &ListModelsArgs) {
+  let manifest = match &args.manifest {
+    Some(path) => Some(read_manifest(path)),
+    None if args.checksum => Some(
+      MANIFEST
+        .iter()
+        .map(|(name, hash)| (name.to_string(), hash.to_string()))
+        .collect(),
+    ),
+    None => None,
+  };
+
+  let dirs = search_dirs(args.model_dir.as_deref());
+  // Embedded models are used right after `--model-dir`
+  let (explicit, rest) = dirs.split_at(usize::from(args.model_dir.is_some()));
+
+  let mut found = scan_dirs(explicit);
+  #[cfg(feature = "embedded-models")]
+  found.extend(
+    crate::embedded::MODELS
+      .iter()
+      .map(|(name, data)| (format!("{name}.pth"), ModelSource::Embedded(data))),
+  );
+  found.extend(scan_dirs(rest));
+
+  println!(
+    "{:<28} {:<5} {:<7} {:<6} {:<10} {:<8} LOCATION",
+    "MODEL", "SCALE", "DENOISE", "FAMILY", "STATUS", "SHA-256"
+  );
+
+  for (idx, (name, source)) in found.iter().enumerate() {
+    let parsed = parse_name(name);
+
+    let status = if found[..idx].iter().any(|(other, _)| other == name) {
+      "shadowed"
+    } else {
+      match &parsed {
+        Some(parsed) => match validate(source, parsed.scale) {
+          Ok(()) => "ok",
+          Err(err) => {
+            // Skip the backtrace attached to the error
+            let err = err.to_string();
+            let reason = err.lines().next().unwrap_or_default();
+            tracing::warn!(location = source.location(), "Invalid model: {reason}");
+            "invalid"
+          }
+        },
+        None => "unknown",
+      }
+    };
+
+    // The manifest lists checkpoints, which embedded safetensors cannot match
+    let checksum = match (&manifest, source) {
+      (Some(manifest), ModelSource::File(path)) => match manifest.get(name) {
+        Some(expected) => {
+          let data = fs::read(path).expect("Failed to read the model");
+          if format!("{:x}", Sha256::digest(data)) == *expected {
+            "ok"
+          } else {
+            "mismatch"
+          }
+        }
+        None => "unknown",
+      },
+      _ => "-",
+    };
+
+    println!(
+      "{:<28} {:<5} {:<7} {:<6} {:<10} {:<8} {}",
+      name,
+      parsed
+        .as_ref()
+        .map_or("?".to_owned(), |parsed| format!("{}x", parsed.scale)),
+      parsed
+        .as_ref()
+        .map_or("?", |parsed| parsed.denoise_level.as_str()),
+      parsed.as_ref().map_or("?", |parsed| parsed.family),
+      status,
+      checksum,
+      source.location(),
+    );
+  }
+
+  for scale in [2, 3] {
+    for denoise_level in DENOISE_LEVELS {
+      let name = model_file_name(denoise_level, scale);
+      if found.iter().all(|(other, _)| *other != name) {
+        println!(
+          "{:<28} {:<5} {:<7} {:<6} {:<10} {:<8} -",
+          name,
+          format!("{scale}x"),
+          denoise_level,
+          "pro",
+          "missing",
+          "-"
+        );
+      }
+    }
+  }
+}
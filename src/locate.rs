@@ -0,0 +1,139 @@
+use std::{
+  env,
+  path::{Path, PathBuf},
+};
+
+use candle_core::{DType, Device};
+use candle_nn::VarBuilder;
+
+use crate::cli::UpscaleArgs;
+
+const MODELS_ENV: &str = "REAL_CUGAN_MODELS";
+
+/// Returns the directories to look for models in, from the highest priority to the lowest:
+///
+/// 1. `--model-dir`
+/// 2. `REAL_CUGAN_MODELS`, which may hold several paths like `PATH`
+/// 3. `models` next to the executable
+/// 4. `real-cugan-rs/models` in the user data directory (`$XDG_DATA_HOME` on Linux)
+/// 5. `real-cugan-rs/models` in each of `$XDG_DATA_DIRS` on Unix
+pub fn search_dirs(model_dir: Option<&Path>) -> Vec<PathBuf> {
+  let mut dirs = vec![];
+
+  if let Some(dir) = model_dir {
+    dirs.push(dir.to_owned());
+  }
+
+  if let Some(paths) = env::var_os(MODELS_ENV) {
+    dirs.extend(env::split_paths(&paths).filter(|path| !path.as_os_str().is_empty()));
+  }
+
+  if let Some(dir) = env::current_exe().ok().as_deref().and_then(Path::parent) {
+    dirs.push(dir.join("models"));
+  }
+
+  if let Some(dir) = dirs::data_dir() {
+    dirs.push(dir.join("real-cugan-rs").join("models"));
+  }
+
+  if cfg!(unix) {
+    let data_dirs = env::var_os("XDG_DATA_DIRS")
+      .filter(|paths| !paths.is_empty())
+      .unwrap_or_else(|| "/usr/local/share:/usr/share".into());
+
+    dirs.extend(env::split_paths(&data_dirs).map(|dir| dir.join("real-cugan-rs").join("models")));
+  }
+
+  dirs
+}
+
+/// Returns the file name of the pro model with the given denoise level and scale.
+pub fn model_file_name(denoise_level: &str, scale: usize) -> String {
+  format!(
+    "pro-{}-up{}x.pth",
+    match denoise_level {
+      "-1" => "conservative".to_owned(),
+      "0" => "no-denoise".to_owned(),
+      d => format!("denoise{d}x"),
+    },
+    scale
+  )
+}
+
+/// Where the weights of a model come from.
+pub enum ModelSource {
+  /// A PyTorch checkpoint on disk.
+  File(PathBuf),
+  /// Safetensors compiled into the binary.
+  #[cfg(feature = "embedded-models")]
+  Embedded(&'static [u8]),
+}
+
+impl ModelSource {
+  pub fn var_builder(&self, device: &Device) -> Result<VarBuilder<'static>, candle_core::Error> {
+    match self {
+      ModelSource::File(path) => VarBuilder::from_pth(path, DType::F32, device),
+      #[cfg(feature = "embedded-models")]
+      ModelSource::Embedded(data) => VarBuilder::from_slice_safetensors(data, DType::F32, device),
+    }
+  }
+
+  pub fn location(&self) -> String {
+    match self {
+      ModelSource::File(path) => path.display().to_string(),
+      #[cfg(feature = "embedded-models")]
+      ModelSource::Embedded(_) => "(embedded)".to_owned(),
+    }
+  }
+}
+
+/// Finds a model by name, logging every searched location if it is not found.
+///
+/// Embedded models are used before the search directories, except `--model-dir`.
+pub fn locate_model(args: &UpscaleArgs, model_name: &str) -> Option<ModelSource> {
+  if let Some(path) = &args.model {
+    if path.is_file() {
+      return Some(ModelSource::File(path.clone()));
+    }
+
+    tracing::error!(?path, "Failed to find the model");
+    return None;
+  }
+
+  let dirs = search_dirs(args.model_dir.as_deref());
+  let find = |dirs: &[PathBuf]| {
+    dirs
+      .iter()
+      .map(|dir| dir.join(model_name))
+      .find(|path| path.is_file())
+  };
+
+  let (explicit, rest) = dirs.split_at(usize::from(args.model_dir.is_some()));
+
+  if let Some(path) = find(explicit) {
+    return Some(ModelSource::File(path));
+  }
+
+  #[cfg(feature = "embedded-models")]
+  if let Some(data) = crate::embedded::find_model(model_name) {
+    tracing::info!(model_name, "Use the embedded model");
+    return Some(ModelSource::Embedded(data));
+  }
+
+  if let Some(path) = find(rest) {
+    return Some(ModelSource::File(path));
+  }
+
+  let searched: Vec<_> = dirs
+    .iter()
+    .map(|dir| format!("  {}", dir.display()))
+    .collect();
+
+  tracing::error!(
+    model_name,
+    "Failed to find the model, searched in:\n{}",
+    searched.join("\n"),
+  );
+
+  None
+}
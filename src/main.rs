@@ -1,26 +1,68 @@
+mod animation;
+mod batch;
 mod cli;
-mod model;
+mod color;
+mod compare;
+#[cfg(feature = "embedded-models")]
+mod embedded;
+mod list_models;
+mod locate;
+mod metadata;
+mod metrics;
+mod pipeline;
+mod plan;
+mod progress;
+mod serve;
 mod setup;
-mod utils;
+mod still;
+mod upscaler;
+mod verify;
+mod watch;
+mod worker;
 
-use std::env;
+use std::time::{Duration, Instant};
 
-use candle_core::{DType, Device, Module, Tensor};
-use candle_nn::VarBuilder;
+use candle_core::{Device, Tensor};
 use clap::Parser;
-use image::{io::Reader as ImageReader, DynamicImage, ImageFormat};
-use resize::Pixel;
-use rgb::FromSlice;
-
-use cli::Cli;
-use model::{RealCugan, UpCunet2x, UpCunet3x};
+use image::ImageFormat;
+use real_cugan_rs::{model, utils};
+
+use animation::{decode_animation, supports_animation, upscale_animation};
+use batch::upscale_dir;
+use cli::{Cli, Command, Filter, UpscaleArgs};
+use compare::compare;
+use list_models::list_models;
+use metrics::psnr;
+use model::{CancelToken, Cancelled};
+use pipeline::upscale;
+use plan::{chain_models, load_models, plan_passes, target_size, Pass};
+use progress::clear_progress_bar;
+use serve::serve;
 use setup::{setup_args, setup_tracing};
-use utils::{preprocess_alpha_channel, save_image};
+use still::{Source, Still};
+use verify::verify;
+use watch::watch;
+use worker::work;
 
 fn main() -> Result<(), candle_core::Error> {
-  let args = Cli::parse();
+  let cli = Cli::parse();
 
-  setup_tracing();
+  setup_tracing(matches!(cli.command, Some(Command::Worker(_))));
+
+  let args = match cli.command {
+    Some(Command::ListModels(args)) => {
+      list_models(&args);
+      return Ok(());
+    }
+    Some(Command::Serve(args)) => return serve(args),
+    Some(Command::Worker(args)) => return work(args),
+    Some(Command::Watch(args)) => return watch(args),
+    Some(Command::Verify(args)) => return verify(args),
+    Some(Command::Compare(args)) => return compare(args),
+    None => cli
+      .upscale
+      .expect("Upscale arguments are required without a subcommand"),
+  };
 
   let output_format = match setup_args(&args) {
     Ok(res) => res,
@@ -30,37 +72,6 @@ fn main() -> Result<(), candle_core::Error> {
     }
   };
 
-  let model_name = format!(
-    "pro-{}-up{}x.pth",
-    match args.denoise_level.as_str() {
-      "-1" => "conservative".to_owned(),
-      "0" => "no-denoise".to_owned(),
-      d => format!("denoise{d}x"),
-    },
-    args.scale
-  );
-
-  let model_path = env::current_exe()?
-    .parent()
-    .expect("Failed to get parent directory of the executable")
-    .join("models")
-    .join(&model_name);
-
-  if !model_path.is_file() {
-    tracing::error!(model_name, "Failed to find the model");
-    return Ok(());
-  }
-
-  let img = ImageReader::open(args.input_path)
-    .expect("Failed to open image file")
-    .decode()
-    .expect("Failed to decode image file");
-
-  let width: usize = img.width().try_into()?;
-  let height: usize = img.height().try_into()?;
-
-  tracing::info!(width, height, "Image file read");
-
   let device = if args.use_cpu {
     Device::Cpu
   } else {
@@ -69,172 +80,140 @@ fn main() -> Result<(), candle_core::Error> {
 
   tracing::info!(?device, "Setup device");
 
-  // TODO: Reduce redundancy here
-  let (rgb, alpha) = match img {
-    DynamicImage::ImageRgb8(img) => {
-      tracing::info!("No alpha channel found");
+  let cancel = args.timeout.map(CancelToken::with_timeout);
 
-      (
-        Tensor::from_vec(img.into_raw(), (height, width, 3), &device)?.to_dtype(DType::F32)?,
-        None,
-      )
-    }
-    DynamicImage::ImageRgba8(img) => {
-      if output_format == ImageFormat::Jpeg {
-        tracing::error!("Images in JPEG format cannot save transparent layers!");
-        return Ok(());
-      }
-
-      tracing::info!("Preprocess the alpha channel...");
-
-      let data = Tensor::from_vec(img.into_raw(), (height, width, 4), &device)?;
-      let (rgb, alpha) = preprocess_alpha_channel(&data)?;
-      (rgb, Some(alpha))
-    }
-    others => {
-      if output_format == ImageFormat::Jpeg {
-        tracing::warn!("The output format is JPEG, Convert into RGB...");
-        let img = others.to_rgb8();
-
-        (
-          Tensor::from_vec(img.into_raw(), (height, width, 3), &device)?.to_dtype(DType::F32)?,
-          None,
-        )
-      } else {
-        tracing::warn!("Convert into RGBA...");
-        let img = others.to_rgba8();
-
-        tracing::info!("Preprocess the alpha channel...");
-
-        let data = Tensor::from_vec(img.into_raw(), (height, width, 4), &device)?;
-        let (rgb, alpha) = preprocess_alpha_channel(&data)?;
-        (rgb, Some(alpha))
-      }
-    }
+  let res = match output_format {
+    Some(output_format) => upscale_file(&args, &device, output_format, cancel.as_ref()),
+    None => upscale_dir(&args, &device, cancel.as_ref()),
   };
 
-  let data = rgb.permute((2, 0, 1))?.unsqueeze(0)?;
-  let data = ((data / (255. / 0.7))? + 0.15)?; // for pro model
-
-  tracing::info!(
-    has_alpha = alpha.is_some(),
-    "Preprocess the image into tensor",
-  );
+  match res {
+    Err(err) => match Cancelled::find(&err) {
+      Some(reason) => {
+        clear_progress_bar();
+        tracing::error!("Upscaling stopped, {reason}");
+        Ok(())
+      }
+      None => Err(err),
+    },
+    Ok(()) => Ok(()),
+  }
+}
 
-  let (target_width, target_height) = match (args.width, args.height) {
-    (Some(w), Some(h)) => (w, h),
-    (Some(w), None) => {
-      let h = (w * height) as f64 / width as f64;
-      (w, h.round() as usize)
-    }
-    (None, Some(h)) => {
-      let w = (h * width) as f64 / height as f64;
-      (w.round() as usize, h)
-    }
-    _ => {
-      let scale: usize = args.scale.into();
-      (width * scale, height * scale)
-    }
-  };
+fn upscale_file(
+  args: &UpscaleArgs,
+  device: &Device,
+  output_format: ImageFormat,
+  cancel: Option<&CancelToken>,
+) -> Result<(), candle_core::Error> {
+  let source = Source::read(args, &args.input_path)?;
+  let plan = plan_passes(args, source.width, source.height);
 
-  let alpha = alpha.map(|alpha| {
-    let mut resizer = resize::new(
-      width,
-      height,
-      target_width,
-      target_height,
-      Pixel::Gray8,
-      resize::Type::Mitchell,
-    )
-    .expect("Failed to initialize the alpha channel resizer");
-
-    let mut dst = vec![0; target_width * target_height];
-
-    resizer
-      .resize(alpha.as_gray(), dst.as_gray_mut())
-      .expect("Failed to upscale the alpha channel");
-
-    tracing::info!("Alpha channel processed");
-
-    dst
-  });
-
-  let vb = VarBuilder::from_pth(model_path, DType::F32, &device)?;
-  let model = match args.scale {
-    2 => RealCugan::X2(UpCunet2x::new(
-      3,
-      3,
-      args.alpha,
-      args.tile_size,
-      !args.no_cache,
-      vb,
-    )?),
-    3 => RealCugan::X3(UpCunet3x::new(
-      3,
-      3,
-      args.alpha,
-      args.tile_size,
-      !args.no_cache,
-      vb,
-    )?),
-    _ => {
-      tracing::error!(scale = args.scale, "Unsupported upscale ratio");
-      return Ok(());
-    }
+  let Some(models) = load_models(&plan, args, device, cancel)? else {
+    return Ok(());
   };
+  let passes = chain_models(&plan, &models);
 
   tracing::info!("Network built");
 
-  let res = model.forward(&data)?;
-  drop(data);
-
-  tracing::info!("Real-CUGAN finished");
+  if let Some(frames) = decode_animation(&args.input_path) {
+    if supports_animation(output_format) {
+      if args.quantize_report {
+        tracing::warn!("The quantisation report is only available for still images");
+      }
 
-  let res = ((res - 0.15)? * (255. / 0.7))?.round()?; // for pro model
-  let res = res.squeeze(0)?.permute((1, 2, 0))?;
+      return upscale_animation(
+        args,
+        &passes,
+        device,
+        frames,
+        source,
+        &args.output_path,
+        output_format,
+      );
+    }
 
-  let cur_width = res.dim(1)?;
-  let cur_height = res.dim(0)?;
+    tracing::warn!("The output format does not support animation, only the first frame is used");
+  }
 
-  let res = if cur_width == target_width && cur_height == target_height {
-    tracing::info!("Skip resampling");
-    res
-  } else {
-    let mut resizer = resize::new(
-      cur_width,
-      cur_height,
-      target_width,
-      target_height,
-      Pixel::RGBF32,
-      resize::Type::Lanczos3,
-    )
-    .expect("Failed to initialize the target resizer");
+  let Some(still) = Still::decode(&args.input_path, source, output_format, device)? else {
+    return Ok(());
+  };
 
-    let src = res.flatten_all()?.to_vec1()?;
-    drop(res);
+  let (target, canvas) = target_size(
+    args,
+    still.source.width,
+    still.source.height,
+    plan.iter().map(|pass| pass.scale).product(),
+  );
 
-    let mut dst = vec![0.; target_width * target_height * 3];
+  let start = Instant::now();
+  let res = upscale(
+    &passes,
+    &still.rgb,
+    None,
+    target.0,
+    target.1,
+    args.filter.unwrap_or(Filter::Lanczos3),
+    args.linear_resize,
+  )?;
 
-    resizer
-      .resize(src.as_rgb(), dst.as_rgb_mut())
-      .expect("Failed to resample the target image");
+  if args.quantize_report {
+    quantize_report(
+      args,
+      &plan,
+      device,
+      cancel,
+      &still.rgb,
+      &res,
+      start.elapsed(),
+    )?;
+  }
 
-    tracing::info!("Image resample to target");
+  still.save(args, &res, target, canvas, &args.output_path, output_format)
+}
 
-    Tensor::from_vec(dst, (target_height, target_width, 3), &device)?
+/// Runs the F32 network on the same input and logs the PSNR of the quantised result against it.
+fn quantize_report(
+  args: &UpscaleArgs,
+  plan: &[Pass],
+  device: &Device,
+  cancel: Option<&CancelToken>,
+  rgb: &Tensor,
+  res: &Tensor,
+  elapsed: Duration,
+) -> Result<(), candle_core::Error> {
+  let plan: Vec<_> = plan
+    .iter()
+    .map(|pass| Pass {
+      quantize: false,
+      ..pass.clone()
+    })
+    .collect();
+
+  let Some(models) = load_models(&plan, args, device, cancel)? else {
+    return Ok(());
   };
-
-  save_image(
-    target_width,
-    target_height,
-    &res,
-    alpha,
-    &args.output_path,
-    output_format,
-    args.lossless,
+  let passes = chain_models(&plan, &models);
+
+  let start = Instant::now();
+  let reference = upscale(
+    &passes,
+    rgb,
+    None,
+    res.dim(1)?,
+    res.dim(0)?,
+    args.filter.unwrap_or(Filter::Lanczos3),
+    args.linear_resize,
   )?;
+  let reference_elapsed = start.elapsed();
 
-  tracing::info!(path = ?args.output_path, "Image saved");
+  tracing::info!(
+    psnr = format!("{:.2} dB", psnr(res, &reference)?),
+    int8_time = ?elapsed,
+    f32_time = ?reference_elapsed,
+    "Quantisation report",
+  );
 
   Ok(())
 }
@@ -0,0 +1,288 @@
+//! In-place access to the few IFD0 tags of a raw EXIF (TIFF) block that upscaling affects.
+
+const ORIENTATION: u16 = 0x0112;
+const X_RESOLUTION: u16 = 0x011a;
+const Y_RESOLUTION: u16 = 0x011b;
+const RESOLUTION_UNIT: u16 = 0x0128;
+
+const TYPE_SHORT: u16 = 3;
+const TYPE_RATIONAL: u16 = 5;
+
+struct Entry {
+  tag: u16,
+  kind: u16,
+  /// Position of the 4-byte value (or value offset) field
+  value_pos: usize,
+}
+
+struct Ifd0 {
+  big_endian: bool,
+  entries: Vec<Entry>,
+}
+
+impl Ifd0 {
+  fn parse(exif: &[u8]) -> Option<Self> {
+    let big_endian = match exif.get(0..2)? {
+      b"MM" => true,
+      b"II" => false,
+      _ => return None,
+    };
+
+    let ifd = Self {
+      big_endian,
+      entries: vec![],
+    };
+
+    if ifd.u16_at(exif, 2)? != 42 {
+      return None;
+    }
+
+    let offset: usize = ifd.u32_at(exif, 4)?.try_into().ok()?;
+    let count = ifd.u16_at(exif, offset)?;
+
+    let entries = (0..usize::from(count))
+      .map(|i| {
+        let pos = offset + 2 + i * 12;
+        Some(Entry {
+          tag: ifd.u16_at(exif, pos)?,
+          kind: ifd.u16_at(exif, pos + 2)?,
+          value_pos: pos + 8,
+        })
+      })
+      .collect::<Option<_>>()?;
+
+    Some(Self { entries, ..ifd })
+  }
+
+  fn find(&self, tag: u16, kind: u16) -> Option<&Entry> {
+    self.entries.iter().find(|e| e.tag == tag && e.kind == kind)
+  }
+
+  fn u16_at(&self, exif: &[u8], pos: usize) -> Option<u16> {
+    let bytes = exif.get(pos..pos + 2)?.try_into().ok()?;
+    Some(if self.big_endian {
+      u16::from_be_bytes(bytes)
+    } else {
+      u16::from_le_bytes(bytes)
+    })
+  }
+
+  fn u32_at(&self, exif: &[u8], pos: usize) -> Option<u32> {
+    let bytes = exif.get(pos..pos + 4)?.try_into().ok()?;
+    Some(if self.big_endian {
+      u32::from_be_bytes(bytes)
+    } else {
+      u32::from_le_bytes(bytes)
+    })
+  }
+
+  fn put_u16(&self, exif: &mut [u8], pos: usize, value: u16) {
+    let bytes = if self.big_endian {
+      value.to_be_bytes()
+    } else {
+      value.to_le_bytes()
+    };
+    exif[pos..pos + 2].copy_from_slice(&bytes);
+  }
+
+  fn put_u32(&self, exif: &mut [u8], pos: usize, value: u32) {
+    let bytes = if self.big_endian {
+      value.to_be_bytes()
+    } else {
+      value.to_le_bytes()
+    };
+    exif[pos..pos + 4].copy_from_slice(&bytes);
+  }
+
+  /// Returns the position of the `(numerator, denominator)` pair of a rational tag.
+  fn rational_pos(&self, exif: &[u8], tag: u16) -> Option<usize> {
+    let entry = self.find(tag, TYPE_RATIONAL)?;
+    let pos: usize = self.u32_at(exif, entry.value_pos)?.try_into().ok()?;
+    exif.get(pos..pos + 8).map(|_| pos)
+  }
+
+  fn rational(&self, exif: &[u8], tag: u16) -> Option<f64> {
+    let pos = self.rational_pos(exif, tag)?;
+    let num = self.u32_at(exif, pos)?;
+    let den = self.u32_at(exif, pos + 4)?;
+    (den != 0).then(|| f64::from(num) / f64::from(den))
+  }
+}
+
+pub fn orientation(exif: &[u8]) -> Option<u16> {
+  let ifd = Ifd0::parse(exif)?;
+  let entry = ifd.find(ORIENTATION, TYPE_SHORT)?;
+  ifd.u16_at(exif, entry.value_pos)
+}
+
+pub fn set_orientation(exif: &mut [u8], orientation: u16) {
+  let Some(ifd) = Ifd0::parse(exif) else {
+    return;
+  };
+
+  if let Some(entry) = ifd.find(ORIENTATION, TYPE_SHORT) {
+    ifd.put_u16(exif, entry.value_pos, orientation);
+  }
+}
+
+/// Returns the resolution in dots per inch.
+pub fn resolution(exif: &[u8]) -> Option<(f64, f64)> {
+  let ifd = Ifd0::parse(exif)?;
+
+  let x = ifd.rational(exif, X_RESOLUTION)?;
+  let y = ifd.rational(exif, Y_RESOLUTION)?;
+
+  let unit = ifd
+    .find(RESOLUTION_UNIT, TYPE_SHORT)
+    .and_then(|entry| ifd.u16_at(exif, entry.value_pos))
+    .unwrap_or(2);
+
+  match unit {
+    2 => Some((x, y)),
+    3 => Some((x * 2.54, y * 2.54)),
+    _ => None,
+  }
+}
+
+/// Swaps the horizontal and vertical resolution tags, for an image turned by a quarter.
+pub fn swap_resolution(exif: &mut [u8]) {
+  let Some(ifd) = Ifd0::parse(exif) else {
+    return;
+  };
+
+  let (Some(x), Some(y)) = (
+    ifd.rational_pos(exif, X_RESOLUTION),
+    ifd.rational_pos(exif, Y_RESOLUTION),
+  ) else {
+    return;
+  };
+
+  let x_value: [u8; 8] = exif[x..x + 8].try_into().unwrap();
+  exif.copy_within(y..y + 8, x);
+  exif[y..y + 8].copy_from_slice(&x_value);
+}
+
+/// Multiplies the resolution tags by the given factors, keeping their unit.
+pub fn scale_resolution(exif: &mut [u8], scale_x: f64, scale_y: f64) {
+  let Some(ifd) = Ifd0::parse(exif) else {
+    return;
+  };
+
+  for (tag, scale) in [(X_RESOLUTION, scale_x), (Y_RESOLUTION, scale_y)] {
+    let Some(pos) = ifd.rational_pos(exif, tag) else {
+      continue;
+    };
+    let Some(value) = ifd.rational(exif, tag) else {
+      continue;
+    };
+
+    // Keep three decimal places, which is far more than any viewer displays
+    let num = (value * scale * 1000.).round().min(f64::from(u32::MAX)) as u32;
+    ifd.put_u32(exif, pos, num);
+    ifd.put_u32(exif, pos + 4, 1000);
+  }
+}
+
+#[cfg(test)]
+pub mod tests {
+  use super::*;
+
+  /// Builds an EXIF block with the orientation and the resolution tags, the values of the
+  /// rationals following the IFD.
+  pub fn exif(big_endian: bool, orientation: u16, x: u32, y: u32, unit: u16) -> Vec<u8> {
+    let u16_bytes = |v: u16| {
+      if big_endian {
+        v.to_be_bytes()
+      } else {
+        v.to_le_bytes()
+      }
+    };
+    let u32_bytes = |v: u32| {
+      if big_endian {
+        v.to_be_bytes()
+      } else {
+        v.to_le_bytes()
+      }
+    };
+
+    let mut out = if big_endian { b"MM" } else { b"II" }.to_vec();
+    out.extend_from_slice(&u16_bytes(42));
+    out.extend_from_slice(&u32_bytes(8));
+
+    // 4 entries of 12 bytes and the offset of the next IFD
+    let values = 8 + 2 + 4 * 12 + 4;
+    out.extend_from_slice(&u16_bytes(4));
+    for (tag, kind, value) in [
+      (ORIENTATION, TYPE_SHORT, u32::from(orientation)),
+      (X_RESOLUTION, TYPE_RATIONAL, values),
+      (Y_RESOLUTION, TYPE_RATIONAL, values + 8),
+      (RESOLUTION_UNIT, TYPE_SHORT, u32::from(unit)),
+    ] {
+      out.extend_from_slice(&u16_bytes(tag));
+      out.extend_from_slice(&u16_bytes(kind));
+      out.extend_from_slice(&u32_bytes(1));
+      if kind == TYPE_SHORT {
+        out.extend_from_slice(&u16_bytes(value as u16));
+        out.extend_from_slice(&[0, 0]);
+      } else {
+        out.extend_from_slice(&u32_bytes(value));
+      }
+    }
+    out.extend_from_slice(&u32_bytes(0));
+
+    for value in [x, y] {
+      out.extend_from_slice(&u32_bytes(value));
+      out.extend_from_slice(&u32_bytes(1));
+    }
+
+    out
+  }
+
+  #[test]
+  fn orientation_is_read_and_set_in_both_byte_orders() {
+    for big_endian in [false, true] {
+      let mut data = exif(big_endian, 6, 72, 96, 2);
+      assert_eq!(orientation(&data), Some(6));
+
+      set_orientation(&mut data, 1);
+      assert_eq!(orientation(&data), Some(1));
+      assert_eq!(resolution(&data), Some((72., 96.)));
+    }
+  }
+
+  #[test]
+  fn resolution_is_scaled_swapped_and_converted() {
+    let mut data = exif(true, 1, 72, 96, 2);
+
+    scale_resolution(&mut data, 2., 3.);
+    assert_eq!(resolution(&data), Some((144., 288.)));
+
+    swap_resolution(&mut data);
+    assert_eq!(resolution(&data), Some((288., 144.)));
+
+    // Dots per centimetre
+    let data = exif(false, 1, 100, 50, 3);
+    assert_eq!(resolution(&data), Some((254., 127.)));
+  }
+
+  #[test]
+  fn truncated_data_is_ignored() {
+    let data = exif(false, 6, 72, 96, 2);
+    // The header, the entry count and the 4 entries
+    let ifd_len = 8 + 2 + 4 * 12;
+
+    for len in 0..data.len() {
+      let mut data = data[..len].to_vec();
+      if len < 10 {
+        assert_eq!(orientation(&data), None);
+      } else if len >= ifd_len {
+        assert_eq!(orientation(&data), Some(6));
+      }
+      assert_eq!(resolution(&data), None);
+
+      set_orientation(&mut data, 1);
+      swap_resolution(&mut data);
+      scale_resolution(&mut data, 2., 2.);
+    }
+  }
+}
@@ -0,0 +1,87 @@
+use super::Metadata;
+
+/// Reads the loop count of the NETSCAPE2.0 application extension, which GIF has no other
+/// metadata of ours besides.
+pub fn read(data: &[u8]) -> Metadata {
+  Metadata {
+    plays: Some(plays(data).unwrap_or(1)),
+    ..Metadata::default()
+  }
+}
+
+/// Returns the number of plays, `None` if the animation has no loop extension and plays once.
+fn plays(data: &[u8]) -> Option<u32> {
+  // header, logical screen descriptor, then the global colour table if its flag is set
+  let flags = *data.get(10)?;
+  let mut pos = 13;
+  if flags & 0x80 != 0 {
+    pos += 3 << ((flags & 0x07) + 1);
+  }
+
+  // The loop extension comes before the first image
+  while *data.get(pos)? == 0x21 {
+    let label = *data.get(pos + 1)?;
+    pos += 2;
+
+    let block_len = usize::from(*data.get(pos)?);
+    let block = data.get(pos + 1..pos + 1 + block_len)?;
+
+    if label == 0xff && matches!(block, b"NETSCAPE2.0" | b"ANIMEXTS1.0") {
+      // sub-block of 3 bytes: id 1, then the 16-bit count of repetitions, 0 for forever
+      let sub = data.get(pos + 1 + block_len..pos + 5 + block_len)?;
+      if sub[0] == 3 && sub[1] == 1 {
+        let repeats = u16::from_le_bytes([sub[2], sub[3]]);
+        return Some(if repeats == 0 {
+          0
+        } else {
+          u32::from(repeats) + 1
+        });
+      }
+    }
+
+    // Skips the sub-blocks up to the terminator
+    while *data.get(pos)? != 0 {
+      pos += usize::from(data[pos]) + 1;
+    }
+    pos += 1;
+  }
+
+  None
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// A GIF header with a global colour table of 2 entries, then the given extensions.
+  fn gif(extensions: &[u8]) -> Vec<u8> {
+    let mut data = b"GIF89a\x01\x00\x01\x00\x80\x00\x00".to_vec();
+    data.extend_from_slice(&[0; 6]);
+    data.extend_from_slice(extensions);
+    data.extend_from_slice(b"\x2c\x00\x00\x00\x00\x01\x00\x01\x00\x00\x02\x02\x44\x01\x00\x3b");
+    data
+  }
+
+  #[test]
+  fn plays_follow_the_loop_extension() {
+    // A graphic control extension comes first
+    let control = b"\x21\xf9\x04\x00\x0a\x00\x00\x00";
+    let netscape = |repeats: u16| {
+      let mut ext = b"\x21\xff\x0bNETSCAPE2.0\x03\x01".to_vec();
+      ext.extend_from_slice(&repeats.to_le_bytes());
+      ext.push(0);
+      ext
+    };
+
+    assert_eq!(read(&gif(control)).plays, Some(1));
+    assert_eq!(
+      read(&gif(&[control, &netscape(0)[..]].concat())).plays,
+      Some(0)
+    );
+    assert_eq!(
+      read(&gif(&[control, &netscape(2)[..]].concat())).plays,
+      Some(3)
+    );
+    assert_eq!(read(&gif(b"")[..20]).plays, Some(1));
+  }
+}
@@ -0,0 +1,230 @@
+use super::Metadata;
+
+const APP0: u8 = 0xe0;
+const APP1: u8 = 0xe1;
+const APP2: u8 = 0xe2;
+const SOS: u8 = 0xda;
+
+const JFIF_PREFIX: &[u8] = b"JFIF\0";
+const EXIF_PREFIX: &[u8] = b"Exif\0\0";
+const XMP_PREFIX: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+const ICC_PREFIX: &[u8] = b"ICC_PROFILE\0";
+
+/// Maximum content length of a segment, excluding the 2-byte length field.
+const MAX_SEGMENT_LEN: usize = 65533;
+
+/// Iterates over the segments before the scan data, yielding `(marker, start, content)` where
+/// `start` is the position of the `0xff` byte.
+fn segments(data: &[u8]) -> impl Iterator<Item = (u8, usize, &[u8])> {
+  let mut pos = 2;
+
+  std::iter::from_fn(move || {
+    if *data.get(pos)? != 0xff {
+      return None;
+    }
+
+    let marker = *data.get(pos + 1)?;
+    if marker == SOS {
+      return None;
+    }
+
+    let len = usize::from(u16::from_be_bytes(
+      data.get(pos + 2..pos + 4)?.try_into().ok()?,
+    ));
+    let content = data.get(pos + 4..pos + 2 + len)?;
+
+    let start = pos;
+    pos += 2 + len;
+    Some((marker, start, content))
+  })
+}
+
+fn write_segment(out: &mut Vec<u8>, marker: u8, parts: &[&[u8]]) {
+  let len: usize = parts.iter().map(|part| part.len()).sum::<usize>() + 2;
+  let len: u16 = len.try_into().expect("JPEG segment exceeds 64KiB");
+
+  out.extend_from_slice(&[0xff, marker]);
+  out.extend_from_slice(&len.to_be_bytes());
+  for part in parts {
+    out.extend_from_slice(part);
+  }
+}
+
+pub fn read(data: &[u8]) -> Metadata {
+  let mut metadata = Metadata::default();
+  let mut icc_parts = vec![];
+
+  for (marker, _, content) in segments(data) {
+    match marker {
+      APP0 if content.starts_with(JFIF_PREFIX) && content.len() >= 12 => {
+        let x = f64::from(u16::from_be_bytes([content[8], content[9]]));
+        let y = f64::from(u16::from_be_bytes([content[10], content[11]]));
+
+        match content[7] {
+          1 => metadata.dpi = Some((x, y)),
+          2 => metadata.dpi = Some((x * 2.54, y * 2.54)),
+          _ => {}
+        }
+      }
+
+      APP1 if content.starts_with(EXIF_PREFIX) => {
+        metadata.exif = Some(content[EXIF_PREFIX.len()..].to_vec());
+      }
+
+      APP1 if content.starts_with(XMP_PREFIX) => {
+        metadata.xmp = Some(content[XMP_PREFIX.len()..].to_vec());
+      }
+
+      APP2 if content.starts_with(ICC_PREFIX) && content.len() > ICC_PREFIX.len() + 2 => {
+        let seq = content[ICC_PREFIX.len()];
+        icc_parts.push((seq, &content[ICC_PREFIX.len() + 2..]));
+      }
+
+      _ => {}
+    }
+  }
+
+  if !icc_parts.is_empty() {
+    icc_parts.sort_by_key(|(seq, _)| *seq);
+    metadata.icc_profile = Some(
+      icc_parts
+        .into_iter()
+        .flat_map(|(_, part)| part)
+        .copied()
+        .collect(),
+    );
+  }
+
+  metadata
+}
+
+pub fn write(data: &[u8], metadata: &Metadata) -> Vec<u8> {
+  let mut out = Vec::with_capacity(data.len());
+  out.extend_from_slice(&data[..2]);
+
+  let mut pos = 2;
+  let mut inserted = false;
+
+  for (marker, start, content) in segments(data) {
+    pos = start + 4 + content.len();
+
+    let keep = match marker {
+      APP1 => !content.starts_with(EXIF_PREFIX) && !content.starts_with(XMP_PREFIX),
+      APP2 => !content.starts_with(ICC_PREFIX),
+      _ => true,
+    };
+
+    if marker == APP0 && content.starts_with(JFIF_PREFIX) && content.len() >= 12 {
+      let mut content = content.to_vec();
+
+      if let Some((x, y)) = metadata.dpi {
+        content[7] = 1;
+        content[8..10].copy_from_slice(&(x.round().min(65535.) as u16).to_be_bytes());
+        content[10..12].copy_from_slice(&(y.round().min(65535.) as u16).to_be_bytes());
+      }
+
+      write_segment(&mut out, marker, &[&content]);
+      continue;
+    }
+
+    // Metadata goes right after the JFIF header
+    if !inserted {
+      write_metadata_segments(&mut out, metadata);
+      inserted = true;
+    }
+
+    if keep {
+      out.extend_from_slice(&data[start..pos]);
+    }
+  }
+
+  if !inserted {
+    write_metadata_segments(&mut out, metadata);
+  }
+
+  out.extend_from_slice(&data[pos..]);
+  out
+}
+
+fn write_metadata_segments(out: &mut Vec<u8>, metadata: &Metadata) {
+  if let Some(exif) = &metadata.exif {
+    if EXIF_PREFIX.len() + exif.len() <= MAX_SEGMENT_LEN {
+      write_segment(out, APP1, &[EXIF_PREFIX, exif]);
+    } else {
+      tracing::warn!("EXIF data is too large for a JPEG segment, skipped");
+    }
+  }
+
+  if let Some(xmp) = &metadata.xmp {
+    if XMP_PREFIX.len() + xmp.len() <= MAX_SEGMENT_LEN {
+      write_segment(out, APP1, &[XMP_PREFIX, xmp]);
+    } else {
+      tracing::warn!("XMP data is too large for a JPEG segment, skipped");
+    }
+  }
+
+  if let Some(profile) = &metadata.icc_profile {
+    let parts: Vec<_> = profile
+      .chunks(MAX_SEGMENT_LEN - ICC_PREFIX.len() - 2)
+      .collect();
+    let count: u8 = parts
+      .len()
+      .try_into()
+      .expect("ICC profile is too large for JPEG");
+
+    for (idx, part) in parts.into_iter().enumerate() {
+      let seq = u8::try_from(idx + 1).unwrap_or(u8::MAX);
+      write_segment(out, APP2, &[ICC_PREFIX, &[seq, count], part]);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use image::{codecs::jpeg::JpegEncoder, RgbImage};
+
+  use super::*;
+  use crate::metadata::tests::sample;
+
+  fn encode(img: &RgbImage) -> Vec<u8> {
+    let mut data = vec![];
+    JpegEncoder::new_with_quality(&mut data, 90)
+      .encode_image(img)
+      .unwrap();
+    data
+  }
+
+  #[test]
+  fn metadata_round_trips() {
+    let img = RgbImage::from_fn(16, 8, |x, y| image::Rgb([x as u8 * 16, y as u8 * 32, 128]));
+    let mut metadata = sample();
+    // Split over two APP2 segments
+    metadata.icc_profile = Some((0..=255).cycle().take(100_000).collect());
+
+    let data = write(&encode(&img), &metadata);
+    let data = write(&data, &metadata);
+
+    let read = read(&data);
+    assert_eq!(read.icc_profile, metadata.icc_profile);
+    assert_eq!(read.exif, metadata.exif);
+    assert_eq!(read.xmp, metadata.xmp);
+    assert_eq!(read.dpi, metadata.dpi);
+
+    let count = |prefix: &[u8]| {
+      segments(&data)
+        .filter(|(_, _, content)| content.starts_with(prefix))
+        .count()
+    };
+    assert_eq!(
+      [JFIF_PREFIX, EXIF_PREFIX, XMP_PREFIX, ICC_PREFIX].map(count),
+      [1, 1, 1, 2]
+    );
+
+    let decoded = image::load_from_memory(&data).unwrap();
+    assert_eq!((decoded.width(), decoded.height()), (16, 8));
+    assert_eq!(
+      decoded.into_rgb8(),
+      image::load_from_memory(&encode(&img)).unwrap().into_rgb8()
+    );
+  }
+}
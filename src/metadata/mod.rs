@@ -0,0 +1,184 @@
+//! Carries colour profiles and metadata from the input file over to the output file.
+//!
+//! The image codecs only deal with pixels, so metadata is read from the raw input bytes and
+//! spliced into the encoded output afterwards.
+
+mod exif;
+mod gif;
+mod jpeg;
+mod png;
+mod webp;
+
+use std::{fs, path::Path};
+
+use image::{DynamicImage, ImageFormat};
+
+#[derive(Default)]
+pub struct Metadata {
+  pub icc_profile: Option<Vec<u8>>,
+  /// Raw EXIF data, starting with the TIFF header
+  pub exif: Option<Vec<u8>>,
+  pub xmp: Option<Vec<u8>>,
+  /// Horizontal and vertical resolution in dots per inch
+  pub dpi: Option<(f64, f64)>,
+  /// How many times an animation plays, 0 meaning forever as in APNG and WebP
+  pub plays: Option<u32>,
+}
+
+impl Metadata {
+  pub fn read(data: &[u8]) -> Self {
+    let mut metadata = match image::guess_format(data) {
+      Ok(ImageFormat::Gif) => gif::read(data),
+      Ok(ImageFormat::Png) => png::read(data),
+      Ok(ImageFormat::Jpeg) => jpeg::read(data),
+      Ok(ImageFormat::WebP) => webp::read(data),
+      _ => Self::default(),
+    };
+
+    if metadata.dpi.is_none() {
+      metadata.dpi = metadata.exif.as_deref().and_then(exif::resolution);
+    }
+
+    metadata
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.icc_profile.is_none() && self.exif.is_none() && self.xmp.is_none() && self.dpi.is_none()
+  }
+
+  pub fn orientation(&self) -> u16 {
+    self
+      .exif
+      .as_deref()
+      .and_then(exif::orientation)
+      .unwrap_or(1)
+  }
+
+  /// Rotates and flips the image upright according to the EXIF orientation, then marks the
+  /// metadata as upright so that viewers don't apply it a second time.
+  pub fn apply_orientation(&mut self, img: DynamicImage) -> DynamicImage {
+    let orientation = self.take_orientation();
+    orient(img, orientation)
+  }
+
+  /// Returns the EXIF orientation for the caller to [`orient`] the pixels with, and marks the
+  /// metadata as upright. Unknown orientations are ignored and returned as 1.
+  pub fn take_orientation(&mut self) -> u16 {
+    let orientation = self.orientation();
+    if !(2..=8).contains(&orientation) {
+      return 1;
+    }
+
+    tracing::info!(orientation, "EXIF orientation applied");
+
+    if let Some(exif) = &mut self.exif {
+      exif::set_orientation(exif, 1);
+    }
+
+    // A quarter turn swaps the axes the resolutions apply to
+    if orientation >= 5 {
+      self.dpi = self.dpi.map(|(x, y)| (y, x));
+      if let Some(exif) = &mut self.exif {
+        exif::swap_resolution(exif);
+      }
+    }
+
+    orientation
+  }
+
+  /// Keeps the physical size of the image unchanged after resizing.
+  pub fn scale_resolution(&mut self, scale_x: f64, scale_y: f64) {
+    self.dpi = self.dpi.map(|(x, y)| (x * scale_x, y * scale_y));
+
+    if let Some(exif) = &mut self.exif {
+      exif::scale_resolution(exif, scale_x, scale_y);
+    }
+  }
+
+  /// Writes the metadata into an image file that was just saved.
+  pub fn write<P: AsRef<Path>>(&self, path: P, format: ImageFormat) {
+    if self.is_empty() {
+      return;
+    }
+
+    let path = path.as_ref();
+
+    let write = match format {
+      ImageFormat::Png => png::write,
+      ImageFormat::Jpeg => jpeg::write,
+      ImageFormat::WebP => webp::write,
+      _ => {
+        tracing::warn!(?format, "The output format cannot carry metadata, skipped");
+        return;
+      }
+    };
+
+    let data = fs::read(path).expect("Failed to read the output file");
+    fs::write(path, write(&data, self)).expect("Failed to write metadata");
+
+    tracing::info!(
+      icc_profile = self.icc_profile.is_some(),
+      exif = self.exif.is_some(),
+      xmp = self.xmp.is_some(),
+      "Metadata written",
+    );
+  }
+}
+
+/// Rotates and flips an image upright according to an EXIF orientation.
+pub fn orient(img: DynamicImage, orientation: u16) -> DynamicImage {
+  match orientation {
+    2 => img.fliph(),
+    3 => img.rotate180(),
+    4 => img.flipv(),
+    5 => img.rotate90().fliph(),
+    6 => img.rotate90(),
+    7 => img.rotate270().fliph(),
+    8 => img.rotate270(),
+    _ => img,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use image::{DynamicImage, GenericImageView, RgbImage};
+
+  use super::*;
+
+  /// Metadata of every kind, with an orientation turning the image by a quarter.
+  pub fn sample() -> Metadata {
+    Metadata {
+      icc_profile: Some((0..=255).cycle().take(1000).collect()),
+      exif: Some(exif::tests::exif(false, 6, 72, 96, 2)),
+      xmp: Some(b"<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"/>".to_vec()),
+      dpi: Some((72., 96.)),
+      plays: None,
+    }
+  }
+
+  #[test]
+  fn orientation_turns_the_image_and_its_resolutions() {
+    let img = DynamicImage::ImageRgb8(RgbImage::from_fn(3, 2, |x, y| {
+      image::Rgb([x as u8, y as u8, 0])
+    }));
+    let mut metadata = sample();
+
+    let img = metadata.apply_orientation(img);
+
+    // Orientation 6 is turned clockwise, the bottom-left pixel going to the top-left corner
+    assert_eq!(img.dimensions(), (2, 3));
+    assert_eq!(img.get_pixel(0, 0).0[..2], [0, 1]);
+    assert_eq!(img.get_pixel(1, 0).0[..2], [0, 0]);
+
+    assert_eq!(metadata.orientation(), 1);
+    assert_eq!(metadata.dpi, Some((96., 72.)));
+    assert_eq!(
+      exif::resolution(metadata.exif.as_deref().unwrap()),
+      Some((96., 72.))
+    );
+
+    // Upright metadata leaves the next image alone
+    let img = metadata.apply_orientation(img);
+    assert_eq!(img.dimensions(), (2, 3));
+  }
+}
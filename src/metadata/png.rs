@@ -0,0 +1,192 @@
+use std::io::{Read, Write};
+
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+
+use super::Metadata;
+
+const SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+const XMP_KEYWORD: &[u8] = b"XML:com.adobe.xmp";
+
+fn chunks(data: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+  let mut pos = SIGNATURE.len();
+
+  std::iter::from_fn(move || {
+    let len: usize = u32::from_be_bytes(data.get(pos..pos + 4)?.try_into().ok()?)
+      .try_into()
+      .ok()?;
+    let kind = data.get(pos + 4..pos + 8)?.try_into().ok()?;
+    let content = data.get(pos + 8..pos + 8 + len)?;
+    pos += len + 12;
+    Some((kind, content))
+  })
+}
+
+fn write_chunk(out: &mut Vec<u8>, kind: &[u8; 4], content: &[u8]) {
+  let len: u32 = content.len().try_into().expect("PNG chunk exceeds 4GiB");
+
+  let mut hasher = crc32fast::Hasher::new();
+  hasher.update(kind);
+  hasher.update(content);
+
+  out.extend_from_slice(&len.to_be_bytes());
+  out.extend_from_slice(kind);
+  out.extend_from_slice(content);
+  out.extend_from_slice(&hasher.finalize().to_be_bytes());
+}
+
+pub fn read(data: &[u8]) -> Metadata {
+  let mut metadata = Metadata::default();
+
+  for (kind, content) in chunks(data) {
+    match &kind {
+      b"iCCP" => {
+        // profile name, null separator, compression method, zlib stream
+        let Some(name_len) = content.iter().position(|&b| b == 0) else {
+          continue;
+        };
+        let mut profile = vec![];
+        if ZlibDecoder::new(&content[(name_len + 2).min(content.len())..])
+          .read_to_end(&mut profile)
+          .is_ok()
+        {
+          metadata.icc_profile = Some(profile);
+        }
+      }
+
+      b"eXIf" => metadata.exif = Some(content.to_vec()),
+
+      // frame count, then the number of plays
+      b"acTL" if content.len() == 8 => {
+        metadata.plays = Some(u32::from_be_bytes(content[4..8].try_into().unwrap()));
+      }
+
+      b"pHYs" if content.len() == 9 && content[8] == 1 => {
+        let x = u32::from_be_bytes(content[0..4].try_into().unwrap());
+        let y = u32::from_be_bytes(content[4..8].try_into().unwrap());
+        metadata.dpi = Some((f64::from(x) * 0.0254, f64::from(y) * 0.0254));
+      }
+
+      b"iTXt" if content.starts_with(XMP_KEYWORD) => {
+        // keyword, null, compression flag, compression method, language tag, null,
+        // translated keyword, null, text
+        let rest = &content[XMP_KEYWORD.len()..];
+        if rest.len() < 3 || rest[0] != 0 || rest[1] != 0 {
+          continue;
+        }
+
+        let mut fields = rest[3..].splitn(3, |&b| b == 0);
+        if let (Some(_), Some(_), Some(text)) = (fields.next(), fields.next(), fields.next()) {
+          metadata.xmp = Some(text.to_vec());
+        }
+      }
+
+      _ => {}
+    }
+  }
+
+  metadata
+}
+
+pub fn write(data: &[u8], metadata: &Metadata) -> Vec<u8> {
+  let mut out = Vec::with_capacity(data.len());
+  out.extend_from_slice(SIGNATURE);
+
+  for (kind, content) in chunks(data) {
+    match &kind {
+      b"iCCP" | b"sRGB" | b"eXIf" | b"pHYs" => continue,
+      b"iTXt" if content.starts_with(XMP_KEYWORD) => continue,
+      _ => {}
+    }
+
+    write_chunk(&mut out, &kind, content);
+
+    if &kind != b"IHDR" {
+      continue;
+    }
+
+    // Ancillary chunks go right after the header, before any palette or image data
+    if let Some(profile) = &metadata.icc_profile {
+      let mut content = b"ICC Profile\0\0".to_vec();
+      let mut encoder = ZlibEncoder::new(&mut content, Compression::default());
+      encoder
+        .write_all(profile)
+        .and_then(|()| encoder.finish().map(drop))
+        .expect("Failed to compress the ICC profile");
+      write_chunk(&mut out, b"iCCP", &content);
+    }
+
+    if let Some((x, y)) = metadata.dpi {
+      let mut content = vec![];
+      content.extend_from_slice(&((x / 0.0254).round() as u32).to_be_bytes());
+      content.extend_from_slice(&((y / 0.0254).round() as u32).to_be_bytes());
+      content.push(1);
+      write_chunk(&mut out, b"pHYs", &content);
+    }
+
+    if let Some(exif) = &metadata.exif {
+      write_chunk(&mut out, b"eXIf", exif);
+    }
+
+    if let Some(xmp) = &metadata.xmp {
+      let mut content = XMP_KEYWORD.to_vec();
+      content.extend_from_slice(b"\0\0\0\0\0");
+      content.extend_from_slice(xmp);
+      write_chunk(&mut out, b"iTXt", &content);
+    }
+  }
+
+  out
+}
+
+#[cfg(test)]
+mod tests {
+  use image::{codecs::png::PngEncoder, ImageEncoder, RgbImage};
+
+  use super::*;
+  use crate::metadata::tests::sample;
+
+  fn encode(img: &RgbImage) -> Vec<u8> {
+    let mut data = vec![];
+    PngEncoder::new(&mut data)
+      .write_image(img, img.width(), img.height(), image::ColorType::Rgb8)
+      .unwrap();
+    data
+  }
+
+  #[test]
+  fn metadata_round_trips() {
+    let img = RgbImage::from_fn(5, 4, |x, y| image::Rgb([x as u8 * 40, y as u8 * 60, 7]));
+    let metadata = sample();
+
+    let data = write(&encode(&img), &metadata);
+    // Writing again replaces the chunks instead of adding more
+    let data = write(&data, &metadata);
+
+    let read = read(&data);
+    assert_eq!(read.icc_profile, metadata.icc_profile);
+    assert_eq!(read.exif, metadata.exif);
+    assert_eq!(read.xmp, metadata.xmp);
+    let (x, y) = read.dpi.unwrap();
+    assert!((x - 72.).abs() < 0.02 && (y - 96.).abs() < 0.02);
+
+    let count = |kind: &[u8; 4]| chunks(&data).filter(|(k, _)| k == kind).count();
+    assert_eq!(
+      [b"iCCP", b"eXIf", b"pHYs", b"iTXt"].map(count),
+      [1, 1, 1, 1]
+    );
+
+    let decoded = image::load_from_memory(&data).unwrap().into_rgb8();
+    assert_eq!(decoded, img);
+  }
+
+  #[test]
+  fn plays_are_read_from_the_animation_control() {
+    let mut data = encode(&RgbImage::new(1, 1));
+    let end = data.len() - 12;
+    let mut actl = vec![];
+    write_chunk(&mut actl, b"acTL", &[0, 0, 0, 1, 0, 0, 0, 3]);
+    data.splice(end..end, actl);
+
+    assert_eq!(read(&data).plays, Some(3));
+  }
+}
@@ -0,0 +1,206 @@
+use super::Metadata;
+
+const FLAG_ICC: u8 = 0x20;
+const FLAG_ALPHA: u8 = 0x10;
+const FLAG_EXIF: u8 = 0x08;
+const FLAG_XMP: u8 = 0x04;
+const FLAG_ANIMATION: u8 = 0x02;
+
+fn chunks(data: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+  let mut pos = 12;
+
+  std::iter::from_fn(move || {
+    let kind = data.get(pos..pos + 4)?.try_into().ok()?;
+    let len: usize = u32::from_le_bytes(data.get(pos + 4..pos + 8)?.try_into().ok()?)
+      .try_into()
+      .ok()?;
+    let content = data.get(pos + 8..pos + 8 + len)?;
+    // Chunks are padded to an even size
+    pos += 8 + len + (len & 1);
+    Some((kind, content))
+  })
+}
+
+fn write_chunk(out: &mut Vec<u8>, kind: &[u8; 4], content: &[u8]) {
+  let len: u32 = content.len().try_into().expect("WebP chunk exceeds 4GiB");
+
+  out.extend_from_slice(kind);
+  out.extend_from_slice(&len.to_le_bytes());
+  out.extend_from_slice(content);
+  if content.len() & 1 == 1 {
+    out.push(0);
+  }
+}
+
+/// Returns the canvas size and whether the bitstream has an alpha channel.
+fn canvas(kind: &[u8; 4], content: &[u8]) -> Option<(u32, u32, bool)> {
+  match kind {
+    b"VP8 " => {
+      // frame tag, start code `9d 01 2a`, then 14-bit width and height
+      if content.get(3..6)? != [0x9d, 0x01, 0x2a] {
+        return None;
+      }
+      let w = u16::from_le_bytes(content.get(6..8)?.try_into().ok()?) & 0x3fff;
+      let h = u16::from_le_bytes(content.get(8..10)?.try_into().ok()?) & 0x3fff;
+      Some((w.into(), h.into(), false))
+    }
+
+    b"VP8L" => {
+      // signature `2f`, then 14-bit width - 1, 14-bit height - 1 and the alpha hint
+      if *content.first()? != 0x2f {
+        return None;
+      }
+      let bits = u32::from_le_bytes(content.get(1..5)?.try_into().ok()?);
+      let w = (bits & 0x3fff) + 1;
+      let h = ((bits >> 14) & 0x3fff) + 1;
+      Some((w, h, bits >> 28 & 1 == 1))
+    }
+
+    _ => None,
+  }
+}
+
+pub fn read(data: &[u8]) -> Metadata {
+  let mut metadata = Metadata::default();
+
+  for (kind, content) in chunks(data) {
+    match &kind {
+      b"ICCP" => metadata.icc_profile = Some(content.to_vec()),
+      b"EXIF" => metadata.exif = Some(content.to_vec()),
+      b"XMP " => metadata.xmp = Some(content.to_vec()),
+      // background colour, then the 16-bit loop count
+      b"ANIM" if content.len() == 6 => {
+        metadata.plays = Some(u16::from_le_bytes([content[4], content[5]]).into());
+      }
+      _ => {}
+    }
+  }
+
+  metadata
+}
+
+pub fn write(data: &[u8], metadata: &Metadata) -> Vec<u8> {
+  let mut flags = 0;
+  let mut size = None;
+  let mut image_chunks = vec![];
+
+  for (kind, content) in chunks(data) {
+    match &kind {
+      b"VP8X" if content.len() >= 10 => {
+        flags = content[0] & (FLAG_ALPHA | FLAG_ANIMATION);
+        let w = u32::from_le_bytes([content[4], content[5], content[6], 0]) + 1;
+        let h = u32::from_le_bytes([content[7], content[8], content[9], 0]) + 1;
+        size = Some((w, h));
+      }
+
+      b"ICCP" | b"EXIF" | b"XMP " => {}
+
+      _ => {
+        if let Some((w, h, alpha)) = canvas(&kind, content) {
+          size.get_or_insert((w, h));
+          if alpha {
+            flags |= FLAG_ALPHA;
+          }
+        }
+        if &kind == b"ALPH" {
+          flags |= FLAG_ALPHA;
+        }
+        image_chunks.push((kind, content));
+      }
+    }
+  }
+
+  let Some((width, height)) = size else {
+    tracing::warn!("Failed to parse the WebP output, metadata skipped");
+    return data.to_vec();
+  };
+
+  if metadata.icc_profile.is_some() {
+    flags |= FLAG_ICC;
+  }
+  if metadata.exif.is_some() {
+    flags |= FLAG_EXIF;
+  }
+  if metadata.xmp.is_some() {
+    flags |= FLAG_XMP;
+  }
+
+  let mut body = b"WEBP".to_vec();
+
+  let mut vp8x = vec![flags, 0, 0, 0];
+  vp8x.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+  vp8x.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+  write_chunk(&mut body, b"VP8X", &vp8x);
+
+  if let Some(profile) = &metadata.icc_profile {
+    write_chunk(&mut body, b"ICCP", profile);
+  }
+
+  for (kind, content) in image_chunks {
+    write_chunk(&mut body, &kind, content);
+  }
+
+  if let Some(exif) = &metadata.exif {
+    write_chunk(&mut body, b"EXIF", exif);
+  }
+
+  if let Some(xmp) = &metadata.xmp {
+    write_chunk(&mut body, b"XMP ", xmp);
+  }
+
+  let len: u32 = body.len().try_into().expect("WebP file exceeds 4GiB");
+
+  let mut out = b"RIFF".to_vec();
+  out.extend_from_slice(&len.to_le_bytes());
+  out.extend_from_slice(&body);
+  out
+}
+
+#[cfg(test)]
+mod tests {
+  use image::{codecs::webp::WebPEncoder, RgbaImage};
+
+  use super::*;
+  use crate::metadata::tests::sample;
+
+  #[test]
+  fn metadata_round_trips() {
+    let img = RgbaImage::from_fn(5, 4, |x, y| {
+      image::Rgba([x as u8 * 40, y as u8 * 60, 7, 200])
+    });
+    let metadata = sample();
+
+    let mut data = vec![];
+    WebPEncoder::new_lossless(&mut data)
+      .encode(&img, img.width(), img.height(), image::ColorType::Rgba8)
+      .unwrap();
+
+    let data = write(&data, &metadata);
+    let data = write(&data, &metadata);
+
+    let read = read(&data);
+    assert_eq!(read.icc_profile, metadata.icc_profile);
+    assert_eq!(read.exif, metadata.exif);
+    assert_eq!(read.xmp, metadata.xmp);
+
+    let (kind, vp8x) = chunks(&data).next().unwrap();
+    assert_eq!(&kind, b"VP8X");
+    assert_eq!(vp8x[0], FLAG_ICC | FLAG_ALPHA | FLAG_EXIF | FLAG_XMP);
+    assert_eq!(canvas(b"VP8X", vp8x), None);
+    assert_eq!(&vp8x[4..], [4, 0, 0, 3, 0, 0]);
+
+    let decoded = image::load_from_memory(&data).unwrap().into_rgba8();
+    assert_eq!(decoded, img);
+  }
+
+  #[test]
+  fn plays_are_read_from_the_animation_chunk() {
+    let mut body = b"WEBP".to_vec();
+    write_chunk(&mut body, b"ANIM", &[0, 0, 0, 0, 5, 0]);
+    let mut data = b"RIFF".to_vec();
+    data.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    data.extend_from_slice(&body);
+
+    assert_eq!(read(&data).plays, Some(5));
+  }
+}
@@ -0,0 +1,192 @@
+use candle_core::Tensor;
+
+/// Side of the Gaussian window of SSIM.
+const WINDOW_SIZE: usize = 11;
+const WINDOW_SIGMA: f64 = 1.5;
+
+/// Weights of the scales of MS-SSIM, from the finest to the coarsest.
+const MS_SSIM_WEIGHTS: [f64; 5] = [0.0448, 0.2856, 0.3001, 0.2363, 0.1333];
+
+/// Smallest side of the images MS-SSIM can be computed on.
+pub const MS_SSIM_MIN_SIZE: usize = WINDOW_SIZE << (MS_SSIM_WEIGHTS.len() - 1);
+
+/// Returns the PSNR in dB between two images of `[0, 255]` values, infinite if they are equal.
+pub fn psnr(a: &Tensor, b: &Tensor) -> Result<f64, candle_core::Error> {
+  let a = a.clamp(0f32, 255f32)?;
+  let b = b.clamp(0f32, 255f32)?;
+
+  let mse: f32 = (a - b)?.sqr()?.mean_all()?.to_scalar()?;
+
+  Ok(10. * (255. * 255. / f64::from(mse)).log10())
+}
+
+/// Returns the SSIM between two `(height, width, 3)` images of `[0, 255]` values, averaged over
+/// the channels. Both sides must fit the window, see [`fits_window`].
+pub fn ssim(a: &Tensor, b: &Tensor) -> Result<f64, candle_core::Error> {
+  let (ssim, _) = ssim_parts(&to_planes(a)?, &to_planes(b)?)?;
+  Ok(ssim)
+}
+
+/// Returns the MS-SSIM between two `(height, width, 3)` images of `[0, 255]` values, whose
+/// sides must be at least [`MS_SSIM_MIN_SIZE`].
+pub fn ms_ssim(a: &Tensor, b: &Tensor) -> Result<f64, candle_core::Error> {
+  let mut a = to_planes(a)?;
+  let mut b = to_planes(b)?;
+  let mut res = 1.;
+
+  for (idx, weight) in MS_SSIM_WEIGHTS.iter().enumerate() {
+    let (ssim, cs) = ssim_parts(&a, &b)?;
+
+    if idx + 1 == MS_SSIM_WEIGHTS.len() {
+      res *= ssim.max(0.).powf(*weight);
+    } else {
+      res *= cs.max(0.).powf(*weight);
+      a = a.avg_pool2d(2)?;
+      b = b.avg_pool2d(2)?;
+    }
+  }
+
+  Ok(res)
+}
+
+/// Returns whether SSIM can be computed on images of this size.
+pub fn fits_window(width: usize, height: usize) -> bool {
+  width.min(height) >= WINDOW_SIZE
+}
+
+/// Converts a `(height, width, 3)` image into `(3, 1, height, width)` planes of clamped values,
+/// which convolve each channel on its own.
+fn to_planes(img: &Tensor) -> Result<Tensor, candle_core::Error> {
+  img
+    .clamp(0f32, 255f32)?
+    .permute((2, 0, 1))?
+    .unsqueeze(1)?
+    .contiguous()
+}
+
+/// Blurs `(n, 1, height, width)` planes with the Gaussian window, without padding.
+fn blur(x: &Tensor) -> Result<Tensor, candle_core::Error> {
+  let center = (WINDOW_SIZE / 2) as f64;
+  let weights: Vec<f32> = (0..WINDOW_SIZE)
+    .map(|i| (-(i as f64 - center).powi(2) / (2. * WINDOW_SIGMA * WINDOW_SIGMA)).exp() as f32)
+    .collect();
+  let sum: f32 = weights.iter().sum();
+  let weights: Vec<f32> = weights.iter().map(|w| w / sum).collect();
+
+  let device = x.device();
+  let row = Tensor::from_slice(&weights, (1, 1, 1, WINDOW_SIZE), device)?;
+  let column = Tensor::from_slice(&weights, (1, 1, WINDOW_SIZE, 1), device)?;
+
+  // The window is separable
+  x.conv2d(&row, 0, 1, 1, 1)?.conv2d(&column, 0, 1, 1, 1)
+}
+
+/// Returns the mean SSIM and the mean contrast-structure term of two sets of planes.
+fn ssim_parts(a: &Tensor, b: &Tensor) -> Result<(f64, f64), candle_core::Error> {
+  const C1: f64 = (0.01 * 255.) * (0.01 * 255.);
+  const C2: f64 = (0.03 * 255.) * (0.03 * 255.);
+
+  let mu_a = blur(a)?;
+  let mu_b = blur(b)?;
+  let mu_aa = mu_a.sqr()?;
+  let mu_bb = mu_b.sqr()?;
+  let mu_ab = (&mu_a * &mu_b)?;
+
+  let sigma_aa = (blur(&a.sqr()?)? - &mu_aa)?;
+  let sigma_bb = (blur(&b.sqr()?)? - &mu_bb)?;
+  let sigma_ab = (blur(&(a * b)?)? - &mu_ab)?;
+
+  let cs = (((sigma_ab * 2.)? + C2)? / ((sigma_aa + sigma_bb)? + C2)?)?;
+  let luminance = (((mu_ab * 2.)? + C1)? / ((mu_aa + mu_bb)? + C1)?)?;
+
+  let ssim: f32 = (luminance * &cs)?.mean_all()?.to_scalar()?;
+  let cs: f32 = cs.mean_all()?.to_scalar()?;
+
+  Ok((f64::from(ssim), f64::from(cs)))
+}
+
+#[cfg(test)]
+mod tests {
+  use candle_core::{Device, Tensor};
+
+  use super::{ms_ssim, psnr, ssim, MS_SSIM_MIN_SIZE};
+
+  /// A `(size, size, 3)` image with values in `[0, 245]`.
+  fn pattern(size: usize) -> Tensor {
+    let data: Vec<f32> = (0..size * size * 3)
+      .map(|i| ((i * 37 + i / 7 * 11) % 246) as f32)
+      .collect();
+
+    Tensor::from_vec(data, (size, size, 3), &Device::Cpu).unwrap()
+  }
+
+  /// A gray `(size, size, 3)` pair with structure at every scale of MS-SSIM: triangle waves plus
+  /// integer noise, the second image being a dimmed and differently noised copy of the first.
+  fn textured_pair(size: usize) -> (Tensor, Tensor) {
+    let tri = |t: usize, period: usize| (t % period).abs_diff(period / 2);
+    let a = |x: usize, y: usize| {
+      40 + 2 * tri(x, 64) + 3 * tri(y, 44) + (x * x * 31 + y * y * 17 + x * y * 7) % 40
+    };
+    let b = |x: usize, y: usize| 3 * a(x, y) / 4 + 30 + (x * 13 + y * y * 5 + x * y) % 25;
+
+    let image = |f: &dyn Fn(usize, usize) -> usize| {
+      let data: Vec<f32> = (0..size)
+        .flat_map(|y| (0..size).flat_map(move |x| [f(x, y) as f32; 3]))
+        .collect();
+      Tensor::from_vec(data, (size, size, 3), &Device::Cpu).unwrap()
+    };
+
+    (image(&a), image(&b))
+  }
+
+  fn flat(size: usize, value: f32) -> Tensor {
+    Tensor::full(value, (size, size, 3), &Device::Cpu).unwrap()
+  }
+
+  #[test]
+  fn equal_images_are_similar() {
+    let a = pattern(MS_SSIM_MIN_SIZE);
+
+    assert!((ssim(&a, &a).unwrap() - 1.).abs() < 1e-6);
+    assert!((ms_ssim(&a, &a).unwrap() - 1.).abs() < 1e-6);
+    assert_eq!(psnr(&a, &a).unwrap(), f64::INFINITY);
+  }
+
+  #[test]
+  fn psnr_of_constant_offset() {
+    let a = pattern(32);
+    let b = (&a + 10.).unwrap();
+
+    // MSE of 100
+    let expected = 10. * (255f64 * 255. / 100.).log10();
+    assert!((psnr(&a, &b).unwrap() - expected).abs() < 1e-9);
+  }
+
+  #[test]
+  fn ssim_of_flat_images() {
+    // Without variance, SSIM is the luminance term of Wang et al. (2004), which is also what
+    // `skimage.metrics.structural_similarity` gives with `gaussian_weights=True, sigma=1.5,
+    // use_sample_covariance=False, data_range=255`
+    const C1: f64 = (0.01 * 255.) * (0.01 * 255.);
+    let expected = (2. * 100. * 110. + C1) / (100f64.powi(2) + 110f64.powi(2) + C1);
+
+    let res = ssim(&flat(16, 100.), &flat(16, 110.)).unwrap();
+    assert!((res - expected).abs() < 1e-4, "{res} instead of {expected}");
+  }
+
+  #[test]
+  fn ssim_and_ms_ssim_of_textured_images() {
+    // Computed in float64 by a direct transcription of `ssim` and `ms_ssim` of pytorch_msssim
+    // (`data_range=255`, 11x11 window of sigma 1.5, `K=(0.01, 0.03)`, no padding), which gives
+    // 0.80471771 for SSIM with sigma 1, and 0.93806491 for MS-SSIM with SSIM instead of the
+    // contrast-structure term at the finer scales
+    let (a, b) = textured_pair(MS_SSIM_MIN_SIZE);
+
+    let small = |t: &Tensor| t.narrow(0, 0, 32).unwrap().narrow(1, 0, 32).unwrap();
+    let res = ssim(&small(&a), &small(&b)).unwrap();
+    assert!((res - 0.81941138).abs() < 1e-5, "SSIM of {res}");
+
+    let res = ms_ssim(&a, &b).unwrap();
+    assert!((res - 0.94282979).abs() < 1e-5, "MS-SSIM of {res}");
+  }
+}
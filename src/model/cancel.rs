@@ -0,0 +1,142 @@
+use std::{
+  fmt, io,
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+  },
+  time::{Duration, Instant},
+};
+
+/// Why inference was stopped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cancelled {
+  /// [`CancelToken::cancel`] was called.
+  Cancelled,
+  /// The timeout of the token has passed.
+  TimedOut,
+}
+
+impl fmt::Display for Cancelled {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Cancelled::Cancelled => f.write_str("inference cancelled"),
+      Cancelled::TimedOut => f.write_str("inference timed out"),
+    }
+  }
+}
+
+impl std::error::Error for Cancelled {}
+
+impl Cancelled {
+  /// Returns the reason if `err` was raised by a [`CancelToken`].
+  ///
+  /// The reason is carried by a [`candle_core::Error::Io`], whose payload keeps its type unlike
+  /// [`candle_core::Error::Wrapped`], possibly with some context around it.
+  pub fn find(err: &candle_core::Error) -> Option<Self> {
+    match err {
+      candle_core::Error::Io(inner) => inner.get_ref()?.downcast_ref::<Cancelled>().copied(),
+      candle_core::Error::Context { inner, .. }
+      | candle_core::Error::WithPath { inner, .. }
+      | candle_core::Error::WithBacktrace { inner, .. } => Self::find(inner),
+      _ => None,
+    }
+  }
+}
+
+/// Stops inference between tiles, stages and the two U-Nets once cancelled or past its
+/// deadline. Clones share the same state.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken {
+  cancelled: Arc<AtomicBool>,
+  deadline: Option<Instant>,
+}
+
+impl CancelToken {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Returns a token that times out after `timeout` from now.
+  pub fn with_timeout(timeout: Duration) -> Self {
+    Self {
+      cancelled: Arc::default(),
+      deadline: Some(Instant::now() + timeout),
+    }
+  }
+
+  pub fn cancel(&self) {
+    self.cancelled.store(true, Ordering::Relaxed);
+  }
+
+  /// Returns why the token stops inference, if it does.
+  pub fn reason(&self) -> Option<Cancelled> {
+    if self.cancelled.load(Ordering::Relaxed) {
+      Some(Cancelled::Cancelled)
+    } else if self
+      .deadline
+      .is_some_and(|deadline| Instant::now() >= deadline)
+    {
+      Some(Cancelled::TimedOut)
+    } else {
+      None
+    }
+  }
+
+  /// Fails with the [`Cancelled`] reason if the token stops inference.
+  pub fn check(&self) -> Result<(), candle_core::Error> {
+    match self.reason() {
+      Some(reason) => {
+        let kind = match reason {
+          Cancelled::Cancelled => io::ErrorKind::Interrupted,
+          Cancelled::TimedOut => io::ErrorKind::TimedOut,
+        };
+        Err(candle_core::Error::Io(io::Error::new(kind, reason)))
+      }
+      None => Ok(()),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use candle_core::{DType, Device, Module, Tensor};
+  use candle_nn::{VarBuilder, VarMap};
+
+  use super::*;
+  use crate::model::RealCugan;
+
+  #[test]
+  fn cancelled_inference_fails_with_the_reason() {
+    let device = Device::Cpu;
+    let x = Tensor::rand(0f32, 1., (1, 3, 32, 32), &device).unwrap();
+
+    for tile_size in [None, Some(16)] {
+      let varmap = VarMap::new();
+      let vb = VarBuilder::from_varmap(&varmap, DType::F32, &device);
+      let mut model = RealCugan::new(2, 1.0, tile_size, true, false, vb).unwrap();
+
+      let cancel = CancelToken::new();
+      model.set_cancel_token(Some(cancel.clone()));
+      assert!(model.forward(&x).is_ok());
+
+      cancel.cancel();
+      let err = model.forward(&x).unwrap_err();
+      assert_eq!(Cancelled::find(&err), Some(Cancelled::Cancelled));
+
+      model.set_cancel_token(Some(CancelToken::with_timeout(Duration::ZERO)));
+      let err = model.forward(&x).unwrap_err().context("upscaling").bt();
+      assert_eq!(Cancelled::find(&err), Some(Cancelled::TimedOut));
+    }
+  }
+
+  #[test]
+  fn other_errors_are_not_cancellations() {
+    let err = candle_core::Error::Msg(Cancelled::Cancelled.to_string()).bt();
+    assert_eq!(Cancelled::find(&err), None);
+
+    // Only the type matters, not the message
+    let other = io::Error::new(io::ErrorKind::TimedOut, Cancelled::TimedOut.to_string());
+    let err = candle_core::Error::Io(other);
+    assert_eq!(Cancelled::find(&err), None);
+  }
+}
@@ -0,0 +1,174 @@
+//! Golden tests: the networks, built with small deterministic weights and run on the CPU on fixed
+//! inputs, must give the outputs of upstream Real-CUGAN.
+//!
+//! `tests/golden/generate.py` computes the references in `tests/golden/upstream.safetensors` with
+//! the PyTorch code of upstream, or with its float64 transcription in `upcunet_f64.py` where
+//! PyTorch is not installed, as recorded in the metadata of the file. Upstream picks its own tile
+//! sizes and rounds the tiled outputs to bytes, so the tiled cases are compared with outputs
+//! recorded from this crate in `tests/golden/references.safetensors` instead. After an intended change of those, rewrite them
+//! with `REAL_CUGAN_BLESS=1 cargo test golden` and review the diff of the reported cases.
+
+use std::{collections::HashMap, env, path::Path};
+
+use candle_core::{DType, Device, Module, Shape, Tensor};
+use candle_nn::{VarBuilder, VarMap};
+
+use crate::model::{
+  unet::{UNet1, UNet2},
+  UpCunet2x, UpCunet3x,
+};
+
+const UPSTREAM: &str = concat!(
+  env!("CARGO_MANIFEST_DIR"),
+  "/tests/golden/upstream.safetensors"
+);
+
+const REFERENCES: &str = concat!(
+  env!("CARGO_MANIFEST_DIR"),
+  "/tests/golden/references.safetensors"
+);
+
+/// Not 1, so that every path has to scale the skip connection of `UNet2`.
+const ALPHA: f64 = 0.8;
+
+/// Deterministic values of a variable, uniform within the bounds of the Kaiming initialisation of
+/// PyTorch so that the activations neither vanish nor explode.
+///
+/// Only integers go into the pattern, which makes it easy to reproduce in other languages.
+fn weights(name: &str, shape: &Shape) -> Tensor {
+  let dims = shape.dims();
+  let bound = if dims.len() == 1 {
+    0.05
+  } else {
+    let fan_in: usize = dims[1..].iter().product();
+    (6. / fan_in as f64).sqrt()
+  };
+
+  let seed = name
+    .bytes()
+    .fold(0, |hash, b| (hash * 31 + b as u64) % 65521);
+  let data: Vec<f32> = (0..shape.elem_count() as u64)
+    .map(|i| {
+      let k = (i * 7919 + seed) % 2003;
+      ((k as f64 / 2002. * 2. - 1.) * bound) as f32
+    })
+    .collect();
+
+  Tensor::from_vec(data, shape, &Device::Cpu).unwrap()
+}
+
+/// Builds a network with [`weights`], the variables being created as the network asks for them.
+fn build<M>(f: impl FnOnce(VarBuilder) -> Result<M, candle_core::Error>) -> M {
+  let varmap = VarMap::new();
+  let model = f(VarBuilder::from_varmap(&varmap, DType::F32, &Device::Cpu)).unwrap();
+
+  for (name, var) in varmap.data().lock().unwrap().iter() {
+    var.set(&weights(name, var.shape())).unwrap();
+  }
+
+  model
+}
+
+/// A `(1, 3, height, width)` input in `[0, 1]`, with edges in every direction.
+fn input(height: usize, width: usize) -> Tensor {
+  let data: Vec<f32> = (0..3)
+    .flat_map(|c| {
+      (0..height)
+        .flat_map(move |y| (0..width).map(move |x| ((x * 13 + y * 7 + c * 50) % 64) as f32 / 63.))
+    })
+    .collect();
+
+  Tensor::from_vec(data, (1, 3, height, width), &Device::Cpu).unwrap()
+}
+
+/// Runs the cases that upstream can run too, named after their reference.
+fn upstream_outputs() -> Vec<(&'static str, Tensor)> {
+  let mut outputs = vec![];
+
+  let unet1 = build(|vb| UNet1::new(3, 3, true, false, false, vb));
+  outputs.push(("unet1", unet1.forward(&input(28, 32)).unwrap()));
+
+  let unet1_x3 = build(|vb| UNet1::new(3, 3, true, true, false, vb));
+  outputs.push(("unet1_x3", unet1_x3.forward(&input(28, 32)).unwrap()));
+
+  let unet2 = build(|vb| UNet2::new(3, 3, true, ALPHA, false, vb));
+  outputs.push(("unet2", unet2.forward(&input(52, 56)).unwrap()));
+
+  // Odd sizes, which are padded before inference and cropped afterwards. PyTorch only reflects
+  // padding narrower than the image.
+  let x = input(21, 23);
+
+  let up2x = build(|vb| UpCunet2x::new(3, 3, ALPHA, None, true, false, vb));
+  outputs.push(("up2x_whole", up2x.forward(&x).unwrap()));
+
+  let up3x = build(|vb| UpCunet3x::new(3, 3, ALPHA, None, true, false, vb));
+  outputs.push(("up3x_whole", up3x.forward(&x).unwrap()));
+
+  outputs
+}
+
+/// Runs the tiled cases, named after their reference.
+fn tiled_outputs() -> Vec<(&'static str, Tensor)> {
+  let mut outputs = vec![];
+  let x = input(13, 17);
+
+  for (name, use_cache) in [("up2x_tiled_cache", true), ("up2x_tiled_no_cache", false)] {
+    let model = build(|vb| UpCunet2x::new(3, 3, ALPHA, Some(12), use_cache, false, vb));
+    outputs.push((name, model.forward(&x).unwrap()));
+  }
+
+  for (name, use_cache) in [("up3x_tiled_cache", true), ("up3x_tiled_no_cache", false)] {
+    let model = build(|vb| UpCunet3x::new(3, 3, ALPHA, Some(12), use_cache, false, vb));
+    outputs.push((name, model.forward(&x).unwrap()));
+  }
+
+  outputs
+}
+
+/// Compares every output with its reference in `path`.
+fn compare(outputs: Vec<(&str, Tensor)>, path: &str) {
+  let references = candle_core::safetensors::load(path, &Device::Cpu).unwrap();
+
+  for (name, output) in outputs {
+    let reference = &references[name];
+    assert_eq!(output.dims(), reference.dims(), "shape of {name}");
+
+    let max = |t: Tensor| t.abs().unwrap().flatten_all().unwrap().max(0).unwrap();
+    let diff = max((&output - reference).unwrap())
+      .to_scalar::<f32>()
+      .unwrap();
+    let scale = max(reference.clone()).to_scalar::<f32>().unwrap().max(1.);
+
+    // Leaves room for the different summation orders of other CPUs and of PyTorch
+    assert!(
+      diff <= 1e-4 * scale,
+      "{name} differs from its reference by {diff}"
+    );
+  }
+}
+
+#[test]
+fn golden_upstream_outputs() {
+  assert!(
+    Path::new(UPSTREAM).exists(),
+    "{UPSTREAM} is missing, generate it with tests/golden/generate.py"
+  );
+
+  compare(upstream_outputs(), UPSTREAM);
+}
+
+#[test]
+fn golden_tiled_outputs() {
+  let outputs = tiled_outputs();
+
+  if env::var_os("REAL_CUGAN_BLESS").is_some() {
+    let references: HashMap<_, _> = outputs
+      .into_iter()
+      .map(|(name, output)| (name.to_owned(), output))
+      .collect();
+    candle_core::safetensors::save(&references, REFERENCES).unwrap();
+    return;
+  }
+
+  compare(outputs, REFERENCES);
+}
@@ -1,8 +1,13 @@
+mod cancel;
+#[cfg(test)]
+mod golden;
 mod unet;
 mod up_cunet;
 
 use candle_core::{Module, Tensor};
+use candle_nn::VarBuilder;
 
+pub use cancel::*;
 pub use up_cunet::*;
 
 pub enum RealCugan {
@@ -18,3 +23,66 @@ impl Module for RealCugan {
     }
   }
 }
+
+impl RealCugan {
+  pub fn new(
+    scale: usize,
+    alpha: f64,
+    tile_size: Option<usize>,
+    use_cache: bool,
+    quantize: bool,
+    vb: VarBuilder,
+  ) -> Result<Self, candle_core::Error> {
+    match scale {
+      2 => Ok(RealCugan::X2(UpCunet2x::new(
+        3, 3, alpha, tile_size, use_cache, quantize, vb,
+      )?)),
+      3 => Ok(RealCugan::X3(UpCunet3x::new(
+        3, 3, alpha, tile_size, use_cache, quantize, vb,
+      )?)),
+      _ => Err(candle_core::Error::Msg(format!("unsupported upscale ratio {scale}")).bt()),
+    }
+  }
+
+  pub fn scale(&self) -> usize {
+    match self {
+      RealCugan::X2(_) => 2,
+      RealCugan::X3(_) => 3,
+    }
+  }
+
+  /// Calls `progress` after each tile of tiled inference.
+  pub fn set_progress(&mut self, progress: Option<ProgressFn>) {
+    match self {
+      RealCugan::X2(m) => m.set_progress(progress),
+      RealCugan::X3(m) => m.set_progress(progress),
+    }
+  }
+
+  /// Stops inference between tiles, stages and the two U-Nets once `cancel` is cancelled or
+  /// times out, failing with a [`Cancelled`] error.
+  pub fn set_cancel_token(&mut self, cancel: Option<CancelToken>) {
+    match self {
+      RealCugan::X2(m) => m.set_cancel_token(cancel),
+      RealCugan::X3(m) => m.set_cancel_token(cancel),
+    }
+  }
+
+  pub fn se_stats(&self, x: &Tensor) -> Result<SeStats, candle_core::Error> {
+    match self {
+      RealCugan::X2(m) => m.se_stats(x),
+      RealCugan::X3(m) => m.se_stats(x),
+    }
+  }
+
+  pub fn forward_with_stats(
+    &self,
+    x: &Tensor,
+    stats: &SeStats,
+  ) -> Result<Tensor, candle_core::Error> {
+    match self {
+      RealCugan::X2(m) => m.forward_with_stats(x, stats),
+      RealCugan::X3(m) => m.forward_with_stats(x, stats),
+    }
+  }
+}
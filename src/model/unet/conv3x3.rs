@@ -0,0 +1,69 @@
+use candle_core::Tensor;
+use candle_nn::{conv2d, Conv2d, Conv2dConfig, VarBuilder};
+
+/// Builds a 3x3 convolution without padding, with its weights rounded to int8 if `quantize` is
+/// set.
+pub fn conv3x3(
+  in_channels: usize,
+  out_channels: usize,
+  quantize: bool,
+  vb: VarBuilder,
+) -> Result<Conv2d, candle_core::Error> {
+  let conv = conv2d(in_channels, out_channels, 3, Conv2dConfig::default(), vb)?;
+
+  if !quantize {
+    return Ok(conv);
+  }
+
+  Ok(Conv2d::new(
+    quantize_per_channel(conv.weight())?,
+    conv.bias().cloned(),
+    *conv.config(),
+  ))
+}
+
+/// Rounds `(out_channels, in_channels, h, w)` weights to symmetric int8 with one scale per output
+/// channel, and returns them dequantised.
+///
+/// candle has no int8 convolution kernel, so the int8 weights run through the F32 kernel.
+fn quantize_per_channel(weight: &Tensor) -> Result<Tensor, candle_core::Error> {
+  let scale = (weight.abs()?.flatten_from(1)?.max_keepdim(1)? / 127.)?
+    .clamp(f32::MIN_POSITIVE, f32::MAX)?
+    .reshape(((), 1, 1, 1))?;
+
+  weight
+    .broadcast_div(&scale)?
+    .round()?
+    .clamp(-127f32, 127f32)?
+    .broadcast_mul(&scale)
+}
+
+#[cfg(test)]
+mod tests {
+  use candle_core::{Device, Tensor};
+
+  use super::quantize_per_channel;
+
+  #[test]
+  fn weights_are_rounded_to_int8_steps_per_channel() {
+    let weight = Tensor::randn(0f32, 0.1, (4, 8, 3, 3), &Device::Cpu).unwrap();
+    let quantized = quantize_per_channel(&weight).unwrap();
+
+    let rows = weight.flatten_from(1).unwrap().to_vec2::<f32>().unwrap();
+    let quantized_rows = quantized.flatten_from(1).unwrap().to_vec2::<f32>().unwrap();
+
+    for (row, quantized_row) in rows.iter().zip(&quantized_rows) {
+      let scale = row.iter().fold(0f32, |max, w| max.max(w.abs())) / 127.;
+
+      for (w, q) in row.iter().zip(quantized_row) {
+        assert!((w - q).abs() <= scale / 2. + 1e-6, "{w} rounded to {q}");
+
+        let steps = q / scale;
+        assert!(
+          (steps - steps.round()).abs() < 1e-3,
+          "{q} is not a multiple of {scale}"
+        );
+      }
+    }
+  }
+}
@@ -0,0 +1,157 @@
+use candle_core::{CpuStorage, CustomOp1, CustomOp2, Layout, Module, Shape, Tensor};
+use candle_nn::{Conv2d, ConvTranspose2d};
+
+const NEGATIVE_SLOPE: f32 = 0.1;
+
+/// LeakyReLU(0.1), in a single pass over the data on CPU.
+pub fn leaky_relu(x: &Tensor) -> Result<Tensor, candle_core::Error> {
+  if x.device().is_cpu() {
+    x.apply_op1_no_bwd(&LeakyRelu)
+  } else {
+    // Same as `max(x, 0) + min(x, 0) * 0.1` since the slope is below 1
+    x.maximum(&(x * f64::from(NEGATIVE_SLOPE))?)
+  }
+}
+
+/// Adds a `(c,)` bias to the channels of `x` and applies LeakyReLU(0.1), in a single pass over
+/// the data on CPU.
+fn bias_leaky_relu(x: &Tensor, bias: &Tensor) -> Result<Tensor, candle_core::Error> {
+  if x.device().is_cpu() {
+    x.apply_op2_no_bwd(bias, &BiasLeakyRelu)
+  } else {
+    leaky_relu(&x.broadcast_add(&bias.reshape((1, (), 1, 1))?)?)
+  }
+}
+
+struct LeakyRelu;
+
+impl CustomOp1 for LeakyRelu {
+  fn name(&self) -> &'static str {
+    "leaky-relu"
+  }
+
+  fn cpu_fwd(
+    &self,
+    storage: &CpuStorage,
+    layout: &Layout,
+  ) -> Result<(CpuStorage, Shape), candle_core::Error> {
+    let (CpuStorage::F32(data), Some((start, end))) = (storage, layout.contiguous_offsets()) else {
+      return Err(
+        candle_core::Error::Msg("leaky-relu expects a contiguous f32 tensor".to_owned()).bt(),
+      );
+    };
+
+    let res = data[start..end]
+      .iter()
+      .map(|&v| if v > 0. { v } else { v * NEGATIVE_SLOPE })
+      .collect();
+
+    Ok((CpuStorage::F32(res), layout.shape().clone()))
+  }
+}
+
+struct BiasLeakyRelu;
+
+impl CustomOp2 for BiasLeakyRelu {
+  fn name(&self) -> &'static str {
+    "bias-leaky-relu"
+  }
+
+  fn cpu_fwd(
+    &self,
+    storage: &CpuStorage,
+    layout: &Layout,
+    bias: &CpuStorage,
+    bias_layout: &Layout,
+  ) -> Result<(CpuStorage, Shape), candle_core::Error> {
+    let (
+      CpuStorage::F32(data),
+      Some((start, end)),
+      CpuStorage::F32(bias),
+      Some((bias_start, bias_end)),
+    ) = (
+      storage,
+      layout.contiguous_offsets(),
+      bias,
+      bias_layout.contiguous_offsets(),
+    )
+    else {
+      return Err(
+        candle_core::Error::Msg("bias-leaky-relu expects contiguous f32 tensors".to_owned()).bt(),
+      );
+    };
+
+    let (_, c, h, w) = layout.shape().dims4()?;
+    let bias = &bias[bias_start..bias_end];
+    if bias.len() != c {
+      return Err(
+        candle_core::Error::Msg(format!(
+          "bias-leaky-relu expects {c} biases, got {}",
+          bias.len()
+        ))
+        .bt(),
+      );
+    }
+
+    let res = data[start..end]
+      .chunks_exact(h * w)
+      .zip(bias.iter().cycle())
+      .flat_map(|(plane, &b)| {
+        plane.iter().map(move |&v| {
+          let v = v + b;
+          if v > 0. {
+            v
+          } else {
+            v * NEGATIVE_SLOPE
+          }
+        })
+      })
+      .collect();
+
+    Ok((CpuStorage::F32(res), layout.shape().clone()))
+  }
+}
+
+/// A convolution followed by LeakyReLU(0.1), with the bias fused into the activation.
+pub struct ConvLeakyRelu(pub Conv2d);
+
+impl Module for ConvLeakyRelu {
+  fn forward(&self, x: &Tensor) -> Result<Tensor, candle_core::Error> {
+    let Some(bias) = self.0.bias() else {
+      return leaky_relu(&self.0.forward(x)?);
+    };
+
+    let config = self.0.config();
+    let x = x.conv2d(
+      self.0.weight(),
+      config.padding,
+      config.stride,
+      config.dilation,
+      config.groups,
+    )?;
+
+    bias_leaky_relu(&x, bias)
+  }
+}
+
+/// A transposed convolution followed by LeakyReLU(0.1), with the bias fused into the activation.
+pub struct ConvTransposeLeakyRelu(pub ConvTranspose2d);
+
+impl Module for ConvTransposeLeakyRelu {
+  fn forward(&self, x: &Tensor) -> Result<Tensor, candle_core::Error> {
+    let Some(bias) = self.0.bias() else {
+      return leaky_relu(&self.0.forward(x)?);
+    };
+
+    let config = self.0.config();
+    let x = x.conv_transpose2d(
+      self.0.weight(),
+      config.padding,
+      config.output_padding,
+      config.stride,
+      config.dilation,
+    )?;
+
+    bias_leaky_relu(&x, bias)
+  }
+}
@@ -1,3 +1,5 @@
+mod conv3x3;
+mod fused;
 mod se_block;
 mod unet1;
 mod unet2;
@@ -6,6 +8,8 @@ mod unet_conv;
 use candle_core::{Module, Tensor};
 use candle_nn::{Conv2d, ConvTranspose2d};
 
+pub use conv3x3::*;
+pub use fused::*;
 pub use se_block::*;
 pub use unet1::*;
 pub use unet2::*;
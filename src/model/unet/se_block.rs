@@ -46,10 +46,14 @@ impl Module for SeBlock {
 
 impl SeBlock {
   pub fn forward_mean(&self, x: &Tensor, x0: &Tensor) -> Result<Tensor, candle_core::Error> {
+    x.broadcast_mul(&self.excitation(x0)?)
+  }
+
+  /// Returns the `(n, c, 1, 1)` channel scales for the given channel means.
+  pub fn excitation(&self, x0: &Tensor) -> Result<Tensor, candle_core::Error> {
     let mut x0 = self.conv1.forward(x0)?;
     x0 = x0.relu()?;
     x0 = self.conv2.forward(&x0)?;
-    x0 = sigmoid(&x0)?;
-    x.broadcast_mul(&x0)
+    sigmoid(&x0)
   }
 }
@@ -1,17 +1,14 @@
 use candle_core::{Module, Tensor};
-use candle_nn::{
-  conv2d, conv_transpose2d, ops::leaky_relu, Conv2d, Conv2dConfig, ConvTranspose2d,
-  ConvTranspose2dConfig, VarBuilder,
-};
+use candle_nn::{conv2d, conv_transpose2d, Conv2dConfig, ConvTranspose2dConfig, VarBuilder};
 
-use super::{ConvBottom, UNetConv};
+use super::{conv3x3, ConvBottom, ConvLeakyRelu, ConvTransposeLeakyRelu, UNetConv};
 
 pub struct UNet1 {
   conv1: UNetConv,
-  conv1_down: Conv2d,
+  conv1_down: ConvLeakyRelu,
   pub conv2: UNetConv,
-  conv2_up: ConvTranspose2d,
-  conv3: Conv2d,
+  conv2_up: ConvTransposeLeakyRelu,
+  conv3: ConvLeakyRelu,
   conv_bottom: ConvBottom,
 }
 
@@ -21,10 +18,11 @@ impl UNet1 {
     out_channels: usize,
     deconv: bool,
     for_x3: bool,
+    quantize: bool,
     vb: VarBuilder,
   ) -> Result<Self, candle_core::Error> {
-    let conv1 = UNetConv::new(in_channels, 32, 64, false, vb.pp("conv1"))?;
-    let conv1_down = conv2d(
+    let conv1 = UNetConv::new(in_channels, 32, 64, false, quantize, vb.pp("conv1"))?;
+    let conv1_down = ConvLeakyRelu(conv2d(
       64,
       64,
       2,
@@ -35,10 +33,10 @@ impl UNet1 {
         groups: 1,
       },
       vb.pp("conv1_down"),
-    )?;
+    )?);
 
-    let conv2 = UNetConv::new(64, 128, 64, true, vb.pp("conv2"))?;
-    let conv2_up = conv_transpose2d(
+    let conv2 = UNetConv::new(64, 128, 64, true, quantize, vb.pp("conv2"))?;
+    let conv2_up = ConvTransposeLeakyRelu(conv_transpose2d(
       64,
       64,
       2,
@@ -49,9 +47,9 @@ impl UNet1 {
         dilation: 1,
       },
       vb.pp("conv2_up"),
-    )?;
+    )?);
 
-    let conv3 = conv2d(64, 64, 3, Conv2dConfig::default(), vb.pp("conv3"))?;
+    let conv3 = ConvLeakyRelu(conv3x3(64, 64, quantize, vb.pp("conv3"))?);
 
     let conv_bottom = if deconv {
       ConvBottom::Deconv(conv_transpose2d(
@@ -67,13 +65,7 @@ impl UNet1 {
         vb.pp("conv_bottom"),
       )?)
     } else {
-      ConvBottom::Else(conv2d(
-        64,
-        out_channels,
-        3,
-        Conv2dConfig::default(),
-        vb.pp("conv_bottom"),
-      )?)
+      ConvBottom::Else(conv3x3(64, out_channels, quantize, vb.pp("conv_bottom"))?)
     };
 
     Ok(Self {
@@ -96,13 +88,10 @@ impl Module for UNet1 {
       .narrow(3, 4, x1.dim(3)? - 8)?
       .narrow(2, 4, x1.dim(2)? - 8)?;
 
-    x2 = leaky_relu(&x2, 0.1)?;
     x2 = self.conv2.forward(&x2)?;
     x2 = self.conv2_up.forward(&x2)?;
-    x2 = leaky_relu(&x2, 0.1)?;
 
-    let mut x3 = self.conv3.forward(&(x1 + x2)?)?;
-    x3 = leaky_relu(&x3, 0.1)?;
+    let x3 = self.conv3.forward(&(x1 + x2)?)?;
 
     self.conv_bottom.forward(&x3)
   }
@@ -117,18 +106,14 @@ impl UNet1 {
       .narrow(3, 4, x1.dim(3)? - 8)?
       .narrow(2, 4, x1.dim(2)? - 8)?;
 
-    x2 = leaky_relu(&x2, 0.1)?;
-    x2 = self.conv2.conv.forward(&x2)?;
+    x2 = self.conv2.forward_conv(&x2)?;
 
     Ok((x1, x2))
   }
 
   pub fn forward_b(&self, x1: &Tensor, x2: &Tensor) -> Result<Tensor, candle_core::Error> {
-    let mut x2 = self.conv2_up.forward(x2)?;
-    x2 = leaky_relu(&x2, 0.1)?;
-
-    let mut x3 = self.conv3.forward(&(x1 + x2)?)?;
-    x3 = leaky_relu(&x3, 0.1)?;
+    let x2 = self.conv2_up.forward(x2)?;
+    let x3 = self.conv3.forward(&(x1 + x2)?)?;
 
     self.conv_bottom.forward(&x3)
   }
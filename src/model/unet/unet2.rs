@@ -1,21 +1,18 @@
 use candle_core::{Module, Tensor};
-use candle_nn::{
-  conv2d, conv_transpose2d, ops::leaky_relu, Conv2d, Conv2dConfig, ConvTranspose2d,
-  ConvTranspose2dConfig, VarBuilder,
-};
+use candle_nn::{conv2d, conv_transpose2d, Conv2dConfig, ConvTranspose2dConfig, VarBuilder};
 
-use super::{ConvBottom, UNetConv};
+use super::{conv3x3, ConvBottom, ConvLeakyRelu, ConvTransposeLeakyRelu, UNetConv};
 
 pub struct UNet2 {
   conv1: UNetConv,
-  conv1_down: Conv2d,
+  conv1_down: ConvLeakyRelu,
   pub conv2: UNetConv,
-  conv2_down: Conv2d,
+  conv2_down: ConvLeakyRelu,
   pub conv3: UNetConv,
-  conv3_up: ConvTranspose2d,
+  conv3_up: ConvTransposeLeakyRelu,
   pub conv4: UNetConv,
-  conv4_up: ConvTranspose2d,
-  conv5: Conv2d,
+  conv4_up: ConvTransposeLeakyRelu,
+  conv5: ConvLeakyRelu,
   conv_bottom: ConvBottom,
   alpha: f64,
 }
@@ -26,6 +23,7 @@ impl UNet2 {
     out_channels: usize,
     deconv: bool,
     alpha: f64,
+    quantize: bool,
     vb: VarBuilder,
   ) -> Result<Self, candle_core::Error> {
     let conf1 = Conv2dConfig {
@@ -41,19 +39,19 @@ impl UNet2 {
       dilation: 1,
     };
 
-    let conv1 = UNetConv::new(in_channels, 32, 64, false, vb.pp("conv1"))?;
-    let conv1_down = conv2d(64, 64, 2, conf1, vb.pp("conv1_down"))?;
+    let conv1 = UNetConv::new(in_channels, 32, 64, false, quantize, vb.pp("conv1"))?;
+    let conv1_down = ConvLeakyRelu(conv2d(64, 64, 2, conf1, vb.pp("conv1_down"))?);
 
-    let conv2 = UNetConv::new(64, 64, 128, true, vb.pp("conv2"))?;
-    let conv2_down = conv2d(128, 128, 2, conf1, vb.pp("conv2_down"))?;
+    let conv2 = UNetConv::new(64, 64, 128, true, quantize, vb.pp("conv2"))?;
+    let conv2_down = ConvLeakyRelu(conv2d(128, 128, 2, conf1, vb.pp("conv2_down"))?);
 
-    let conv3 = UNetConv::new(128, 256, 128, true, vb.pp("conv3"))?;
-    let conv3_up = conv_transpose2d(128, 128, 2, conf2, vb.pp("conv3_up"))?;
+    let conv3 = UNetConv::new(128, 256, 128, true, quantize, vb.pp("conv3"))?;
+    let conv3_up = ConvTransposeLeakyRelu(conv_transpose2d(128, 128, 2, conf2, vb.pp("conv3_up"))?);
 
-    let conv4 = UNetConv::new(128, 64, 64, true, vb.pp("conv4"))?;
-    let conv4_up = conv_transpose2d(64, 64, 2, conf2, vb.pp("conv4_up"))?;
+    let conv4 = UNetConv::new(128, 64, 64, true, quantize, vb.pp("conv4"))?;
+    let conv4_up = ConvTransposeLeakyRelu(conv_transpose2d(64, 64, 2, conf2, vb.pp("conv4_up"))?);
 
-    let conv5 = conv2d(64, 64, 3, Conv2dConfig::default(), vb.pp("conv5"))?;
+    let conv5 = ConvLeakyRelu(conv3x3(64, 64, quantize, vb.pp("conv5"))?);
 
     let conv_bottom = if deconv {
       ConvBottom::Deconv(conv_transpose2d(
@@ -69,13 +67,7 @@ impl UNet2 {
         vb.pp("conv_bottom"),
       )?)
     } else {
-      ConvBottom::Else(conv2d(
-        64,
-        out_channels,
-        3,
-        Conv2dConfig::default(),
-        vb.pp("conv_bottom"),
-      )?)
+      ConvBottom::Else(conv3x3(64, out_channels, quantize, vb.pp("conv_bottom"))?)
     };
 
     Ok(Self {
@@ -103,7 +95,6 @@ impl Module for UNet2 {
       .narrow(3, 16, x1.dim(3)? - 32)?
       .narrow(2, 16, x1.dim(2)? - 32)?;
 
-    x2 = leaky_relu(&x2, 0.1)?;
     x2 = self.conv2.forward(&x2)?;
 
     let mut x3 = self.conv2_down.forward(&x2)?;
@@ -112,18 +103,14 @@ impl Module for UNet2 {
       .narrow(3, 4, x2.dim(3)? - 8)?
       .narrow(2, 4, x2.dim(2)? - 8)?;
 
-    x3 = leaky_relu(&x3, 0.1)?;
     x3 = self.conv3.forward(&x3)?;
     x3 = self.conv3_up.forward(&x3)?;
-    x3 = leaky_relu(&x3, 0.1)?;
 
     let mut x4 = self.conv4.forward(&(x2 + x3)?)?;
     x4 = (x4 * self.alpha)?;
     x4 = self.conv4_up.forward(&x4)?;
-    x4 = leaky_relu(&x4, 0.1)?;
 
-    let mut x5 = self.conv5.forward(&(x1 + x4)?)?;
-    x5 = leaky_relu(&x5, 0.1)?;
+    let x5 = self.conv5.forward(&(x1 + x4)?)?;
 
     self.conv_bottom.forward(&x5)
   }
@@ -138,8 +125,7 @@ impl UNet2 {
       .narrow(3, 16, x1.dim(3)? - 32)?
       .narrow(2, 16, x1.dim(2)? - 32)?;
 
-    x2 = leaky_relu(&x2, 0.1)?;
-    x2 = self.conv2.conv.forward(&x2)?;
+    x2 = self.conv2.forward_conv(&x2)?;
 
     Ok((x1, x2))
   }
@@ -151,24 +137,19 @@ impl UNet2 {
       .narrow(3, 4, x2.dim(3)? - 8)?
       .narrow(2, 4, x2.dim(2)? - 8)?;
 
-    x3 = leaky_relu(&x3, 0.1)?;
-    x3 = self.conv3.conv.forward(&x3)?;
+    x3 = self.conv3.forward_conv(&x3)?;
 
     Ok((x2, x3))
   }
 
   pub fn forward_c(&self, x2: &Tensor, x3: &Tensor) -> Result<Tensor, candle_core::Error> {
-    let mut x3 = self.conv3_up.forward(x3)?;
-    x3 = leaky_relu(&x3, 0.1)?;
-    self.conv4.conv.forward(&(x2 + x3)?)
+    let x3 = self.conv3_up.forward(x3)?;
+    self.conv4.forward_conv(&(x2 + x3)?)
   }
 
   pub fn forward_d(&self, x1: &Tensor, x4: &Tensor) -> Result<Tensor, candle_core::Error> {
-    let mut x4 = self.conv4_up.forward(x4)?;
-    x4 = leaky_relu(&x4, 0.1)?;
-
-    let mut x5 = self.conv5.forward(&(x1 + x4)?)?;
-    x5 = leaky_relu(&x5, 0.1)?;
+    let x4 = self.conv4_up.forward(x4)?;
+    let x5 = self.conv5.forward(&(x1 + x4)?)?;
 
     self.conv_bottom.forward(&x5)
   }
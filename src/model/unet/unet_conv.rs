@@ -1,10 +1,10 @@
 use candle_core::{Module, Tensor};
-use candle_nn::{conv2d, seq, Activation, Conv2dConfig, Sequential, VarBuilder};
+use candle_nn::VarBuilder;
 
-use super::SeBlock;
+use super::{conv3x3, ConvLeakyRelu, SeBlock};
 
 pub struct UNetConv {
-  pub conv: Sequential,
+  pub conv: [ConvLeakyRelu; 2],
   pub seblock: Option<SeBlock>,
 }
 
@@ -14,29 +14,23 @@ impl UNetConv {
     mid_channels: usize,
     out_channels: usize,
     se: bool,
+    quantize: bool,
     vb: VarBuilder,
   ) -> Result<Self, candle_core::Error> {
-    let mut conv = seq();
-
-    conv = conv.add(conv2d(
-      in_channels,
-      mid_channels,
-      3,
-      Conv2dConfig::default(),
-      vb.pp("conv.0"),
-    )?);
-
-    conv = conv.add(Activation::LeakyRelu(0.1));
-
-    conv = conv.add(conv2d(
-      mid_channels,
-      out_channels,
-      3,
-      Conv2dConfig::default(),
-      vb.pp("conv.2"),
-    )?);
-
-    conv = conv.add(Activation::LeakyRelu(0.1));
+    let conv = [
+      ConvLeakyRelu(conv3x3(
+        in_channels,
+        mid_channels,
+        quantize,
+        vb.pp("conv.0"),
+      )?),
+      ConvLeakyRelu(conv3x3(
+        mid_channels,
+        out_channels,
+        quantize,
+        vb.pp("conv.2"),
+      )?),
+    ];
 
     let seblock = if se {
       Some(SeBlock::new(out_channels, 8, true, vb.pp("seblock"))?)
@@ -48,9 +42,16 @@ impl UNetConv {
   }
 }
 
+impl UNetConv {
+  /// Runs the convolutions without the SE block.
+  pub fn forward_conv(&self, x: &Tensor) -> Result<Tensor, candle_core::Error> {
+    self.conv[1].forward(&self.conv[0].forward(x)?)
+  }
+}
+
 impl Module for UNetConv {
   fn forward(&self, x: &Tensor) -> Result<Tensor, candle_core::Error> {
-    let mut z = self.conv.forward(x)?;
+    let mut z = self.forward_conv(x)?;
 
     if let Some(seblock) = &self.seblock {
       z = seblock.forward(&z)?;
@@ -1,5 +1,207 @@
 mod up_cunet_2x;
 mod up_cunet_3x;
 
+use candle_core::Tensor;
+use smallvec::SmallVec;
+
 pub use up_cunet_2x::*;
 pub use up_cunet_3x::*;
+
+use crate::model::unet::{SeBlock, UNet1, UNet2};
+
+/// The means fed into the four SE blocks (`unet1.conv2`, `unet2.conv2`, `unet2.conv3` and
+/// `unet2.conv4`), each of shape `(n, c, 1, 1)`.
+///
+/// Reusing the statistics of one image for a series of similar images (e.g. the frames of an
+/// animation) keeps the global colour response of the network stable between them.
+#[derive(Clone)]
+pub struct SeStats(pub [Tensor; 4]);
+
+/// Intermediate results of each tile, kept between the stages of tiled inference.
+type TileCache = Vec<SmallVec<[Tensor; 4]>>;
+
+/// Progress of tiled inference, reported after each tile.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Progress {
+  /// From 1 to [`Progress::STAGE_NUM`]. The first four stages collect the SE statistics and are
+  /// skipped when the statistics are given.
+  pub stage: usize,
+  /// Index of the finished tile in the stage, starting from 1
+  pub tile: usize,
+  pub tile_num: usize,
+}
+
+impl Progress {
+  pub const STAGE_NUM: usize = 5;
+}
+
+/// Receives the [`Progress`] of tiled inference.
+pub type ProgressFn = Box<dyn Fn(Progress) + Send + Sync>;
+
+fn seblocks<'a>(
+  unet1: &'a UNet1,
+  unet2: &'a UNet2,
+) -> Result<[&'a SeBlock; 4], candle_core::Error> {
+  let Some(seblock12) = &unet1.conv2.seblock else {
+    return Err(candle_core::Error::Msg("`unet1.conv2` has no seblock".to_owned()).bt());
+  };
+
+  let Some(seblock22) = &unet2.conv2.seblock else {
+    return Err(candle_core::Error::Msg("`unet2.conv2` has no seblock".to_owned()).bt());
+  };
+
+  let Some(seblock23) = &unet2.conv3.seblock else {
+    return Err(candle_core::Error::Msg("`unet2.conv3` has no seblock".to_owned()).bt());
+  };
+
+  let Some(seblock24) = &unet2.conv4.seblock else {
+    return Err(candle_core::Error::Msg("`unet2.conv4` has no seblock".to_owned()).bt());
+  };
+
+  Ok([seblock12, seblock22, seblock23, seblock24])
+}
+
+/// The channel scales of the four SE blocks for the means in [`SeStats`].
+///
+/// They are the same for every tile of an image, so they are computed once per image instead of
+/// once per tile.
+struct SeScales([Tensor; 4]);
+
+impl SeScales {
+  fn new(unet1: &UNet1, unet2: &UNet2, stats: &SeStats) -> Result<Self, candle_core::Error> {
+    let [seblock12, seblock22, seblock23, seblock24] = seblocks(unet1, unet2)?;
+
+    Ok(Self([
+      seblock12.excitation(&stats.0[0])?,
+      seblock22.excitation(&stats.0[1])?,
+      seblock23.excitation(&stats.0[2])?,
+      seblock24.excitation(&stats.0[3])?,
+    ]))
+  }
+}
+
+/// Applies an SE block to `x`, scaling the channels by `scale` if given, or by the SE block
+/// applied to the means of `x` otherwise, which are returned.
+fn apply_se(
+  seblock: &SeBlock,
+  x: &Tensor,
+  scale: Option<&Tensor>,
+) -> Result<(Tensor, Option<Tensor>), candle_core::Error> {
+  match scale {
+    Some(scale) => Ok((x.broadcast_mul(scale)?, None)),
+    None => {
+      let mean = x.mean_keepdim((2, 3))?;
+      Ok((seblock.forward_mean(x, &mean)?, Some(mean)))
+    }
+  }
+}
+
+/// Runs a padded crop through everything before the last SE block.
+///
+/// The SE blocks use the scales in `scales`, or the crop's own means when `scales` is `None`.
+/// Returns `(opt_unet1, tmp_x1, tmp_x4)`, along with the means of the crop if they were used.
+fn forward_crop_head(
+  unet1: &UNet1,
+  unet2: &UNet2,
+  alpha: f64,
+  x: &Tensor,
+  scales: Option<&SeScales>,
+) -> Result<(Tensor, Tensor, Tensor, Option<SeStats>), candle_core::Error> {
+  let [seblock12, seblock22, seblock23, _] = seblocks(unet1, unet2)?;
+  let scale = |i: usize| scales.map(|scales| &scales.0[i]);
+
+  let (tmp0, x_crop) = unet1.forward_a(x)?;
+  let (x_crop, se_mean0) = apply_se(seblock12, &x_crop, scale(0))?;
+  let opt_unet1 = unet1.forward_b(&tmp0, &x_crop)?;
+
+  let (tmp_x1, tmp_x2) = unet2.forward_a(&opt_unet1)?;
+  let (tmp_x2, se_mean1) = apply_se(seblock22, &tmp_x2, scale(1))?;
+
+  let (tmp_x2, tmp_x3) = unet2.forward_b(&tmp_x2)?;
+  let (tmp_x3, se_mean2) = apply_se(seblock23, &tmp_x3, scale(2))?;
+
+  let mut tmp_x4 = unet2.forward_c(&tmp_x2, &tmp_x3)?;
+  tmp_x4 = (tmp_x4 * alpha)?;
+
+  let stats = match (se_mean0, se_mean1, se_mean2) {
+    (Some(se_mean0), Some(se_mean1), Some(se_mean2)) => {
+      let se_mean3 = tmp_x4.mean_keepdim((2, 3))?;
+      Some(SeStats([se_mean0, se_mean1, se_mean2, se_mean3]))
+    }
+    _ => None,
+  };
+
+  Ok((opt_unet1, tmp_x1, tmp_x4, stats))
+}
+
+/// Finishes a crop started by [`forward_crop_head`], `se_scale3` being the scales of the last SE
+/// block.
+fn forward_crop_tail(
+  unet1_out: &Tensor,
+  unet2: &UNet2,
+  tmp_x1: &Tensor,
+  tmp_x4: &Tensor,
+  se_scale3: &Tensor,
+) -> Result<Tensor, candle_core::Error> {
+  let x_crop =
+    unet1_out
+      .narrow(3, 20, unet1_out.dim(3)? - 40)?
+      .narrow(2, 20, unet1_out.dim(2)? - 40)?;
+
+  let tmp_x4 = tmp_x4.broadcast_mul(se_scale3)?;
+  let x0 = unet2.forward_d(tmp_x1, &tmp_x4)?;
+  x0.add(&x_crop)
+}
+
+#[cfg(test)]
+mod tests {
+  use candle_core::{DType, Device, IndexOp, Module, Tensor};
+  use candle_nn::{VarBuilder, VarMap};
+
+  use crate::model::RealCugan;
+
+  /// Each image of a batch must use its own SE statistics, so running a batch gives the same
+  /// result as running its images one by one, up to rounding.
+  #[test]
+  fn batch_matches_single_images() {
+    let device = Device::Cpu;
+
+    // Scale the images differently so that mixing their statistics changes the result
+    let x = Tensor::rand(0f32, 1., (3, 3, 40, 56), &device).unwrap();
+    let x = x
+      .broadcast_mul(
+        &Tensor::new(&[0.2f32, 0.6, 1.], &device)
+          .unwrap()
+          .reshape((3, 1, 1, 1))
+          .unwrap(),
+      )
+      .unwrap();
+
+    for (scale, tile_size) in [(2, None), (2, Some(16)), (3, None), (3, Some(20))] {
+      let varmap = VarMap::new();
+      let vb = VarBuilder::from_varmap(&varmap, DType::F32, &device);
+      let model = RealCugan::new(scale, 1.0, tile_size, true, false, vb).unwrap();
+
+      let batch = model.forward(&x).unwrap();
+
+      for idx in 0..3 {
+        let single = model.forward(&x.i(idx..idx + 1).unwrap()).unwrap();
+        let diff = (batch.i(idx..idx + 1).unwrap() - single)
+          .unwrap()
+          .abs()
+          .unwrap()
+          .flatten_all()
+          .unwrap()
+          .max(0)
+          .unwrap()
+          .to_scalar::<f32>()
+          .unwrap();
+
+        assert!(
+          diff < 1e-4,
+          "image {idx} of {scale}x with tile size {tile_size:?} differs by {diff}"
+        );
+      }
+    }
+  }
+}
@@ -1,10 +1,15 @@
 use candle_core::{DType, IndexOp, Module, Tensor};
 use candle_nn::VarBuilder;
-use smallvec::{smallvec, SmallVec};
+use smallvec::smallvec;
 
+use super::{
+  forward_crop_head, forward_crop_tail, seblocks, Progress, ProgressFn, SeScales, SeStats,
+  TileCache,
+};
+use crate::model::CancelToken;
 use crate::{
   model::unet::{UNet1, UNet2},
-  utils::TensorExt,
+  utils::{PadMode, TensorExt},
 };
 
 pub struct UpCunet2x {
@@ -13,6 +18,8 @@ pub struct UpCunet2x {
   alpha: f64,
   tile_size: Option<usize>,
   use_cache: bool,
+  progress: Option<ProgressFn>,
+  cancel: Option<CancelToken>,
 }
 
 impl UpCunet2x {
@@ -22,10 +29,25 @@ impl UpCunet2x {
     alpha: f64,
     tile_size: Option<usize>,
     use_cache: bool,
+    quantize: bool,
     vb: VarBuilder,
   ) -> Result<Self, candle_core::Error> {
-    let unet1 = UNet1::new(in_channels, out_channels, true, false, vb.pp("unet1"))?;
-    let unet2 = UNet2::new(in_channels, out_channels, false, alpha, vb.pp("unet2"))?;
+    let unet1 = UNet1::new(
+      in_channels,
+      out_channels,
+      true,
+      false,
+      quantize,
+      vb.pp("unet1"),
+    )?;
+    let unet2 = UNet2::new(
+      in_channels,
+      out_channels,
+      false,
+      alpha,
+      quantize,
+      vb.pp("unet2"),
+    )?;
 
     if let Some(tile_size) = tile_size {
       if tile_size % 2 != 0 {
@@ -39,8 +61,38 @@ impl UpCunet2x {
       alpha,
       tile_size,
       use_cache,
+      progress: None,
+      cancel: None,
     })
   }
+
+  /// Calls `progress` after each tile of tiled inference.
+  pub fn set_progress(&mut self, progress: Option<ProgressFn>) {
+    self.progress = progress;
+  }
+
+  /// Stops inference between tiles, stages and the two U-Nets once `cancel` is cancelled.
+  pub fn set_cancel_token(&mut self, cancel: Option<CancelToken>) {
+    self.cancel = cancel;
+  }
+
+  fn check_cancelled(&self) -> Result<(), candle_core::Error> {
+    match &self.cancel {
+      Some(cancel) => cancel.check(),
+      None => Ok(()),
+    }
+  }
+
+  /// Reports that the tile at `idx` of the stage is finished.
+  fn report(&self, stage: usize, idx: usize, tile_num: usize) {
+    if let Some(progress) = &self.progress {
+      progress(Progress {
+        stage,
+        tile: idx + 1,
+        tile_num,
+      });
+    }
+  }
 }
 
 impl Module for UpCunet2x {
@@ -51,15 +103,12 @@ impl Module for UpCunet2x {
 
     let (_, _, h0, w0) = x.shape().dims4()?;
 
-    let ph = ((h0 - 1) / 2 + 1) * 2;
-    let pw = ((w0 - 1) / 2 + 1) * 2;
-
-    let mut x = x
-      .reflection_pad(3, 18, 18 + pw - w0)?
-      .reflection_pad(2, 18, 18 + ph - h0)?;
+    let mut x = Self::pad_whole(x)?;
 
+    self.check_cancelled()?;
     x = self.unet1.forward(&x)?;
 
+    self.check_cancelled()?;
     let x0 = self.unet2.forward(&x)?;
 
     x = x
@@ -68,7 +117,7 @@ impl Module for UpCunet2x {
 
     x = x0.add(&x)?;
 
-    if w0 != pw || h0 != ph {
+    if x.dim(3)? != w0 * 2 || x.dim(2)? != h0 * 2 {
       x = x.narrow(3, 0, w0 * 2)?.narrow(2, 0, h0 * 2)?;
     }
 
@@ -77,34 +126,121 @@ impl Module for UpCunet2x {
 }
 
 impl UpCunet2x {
+  /// Computes the SE statistics that [`Module::forward`] would use for `x`.
+  pub fn se_stats(&self, x: &Tensor) -> Result<SeStats, candle_core::Error> {
+    if let Some(tile_size) = self.tile_size {
+      let x = Self::pad_tile(x, tile_size)?;
+      return Ok(self.tile_stages(&x, tile_size)?.0);
+    }
+
+    let x = Self::pad_whole(x)?;
+    self.check_cancelled()?;
+    Ok(
+      forward_crop_head(&self.unet1, &self.unet2, self.alpha, &x, None)?
+        .3
+        .expect("Without scales the crop computes its own statistics"),
+    )
+  }
+
+  /// Same as [`Module::forward`], but the SE blocks use `stats` instead of the means of `x`.
+  pub fn forward_with_stats(
+    &self,
+    x: &Tensor,
+    stats: &SeStats,
+  ) -> Result<Tensor, candle_core::Error> {
+    let (_, _, h0, w0) = x.shape().dims4()?;
+
+    let mut res = if let Some(tile_size) = self.tile_size {
+      let x = Self::pad_tile(x, tile_size)?;
+      self.tile_tail(&x, tile_size, stats, None)?
+    } else {
+      let x = Self::pad_whole(x)?;
+      let scales = SeScales::new(&self.unet1, &self.unet2, stats)?;
+      self.check_cancelled()?;
+      let (opt_unet1, tmp_x1, tmp_x4, _) =
+        forward_crop_head(&self.unet1, &self.unet2, self.alpha, &x, Some(&scales))?;
+      self.check_cancelled()?;
+      forward_crop_tail(&opt_unet1, &self.unet2, &tmp_x1, &tmp_x4, &scales.0[3])?
+    };
+
+    if res.dim(3)? != w0 * 2 || res.dim(2)? != h0 * 2 {
+      res = res.narrow(3, 0, w0 * 2)?.narrow(2, 0, h0 * 2)?;
+    }
+
+    Ok(res)
+  }
+
+  fn pad_whole(x: &Tensor) -> Result<Tensor, candle_core::Error> {
+    let (_, _, h0, w0) = x.shape().dims4()?;
+
+    let ph = ((h0 - 1) / 2 + 1) * 2;
+    let pw = ((w0 - 1) / 2 + 1) * 2;
+
+    x.pad(
+      2,
+      &[(18, 18 + ph - h0), (18, 18 + pw - w0)],
+      PadMode::Reflect,
+    )
+  }
+
+  fn pad_tile(x: &Tensor, tile_size: usize) -> Result<Tensor, candle_core::Error> {
+    let (_, _, h0, w0) = x.shape().dims4()?;
+
+    let ph = ((h0 - 1) / tile_size + 1) * tile_size;
+    let pw = ((w0 - 1) / tile_size + 1) * tile_size;
+
+    x.pad(
+      2,
+      &[(18, 18 + ph - h0), (18, 18 + pw - w0)],
+      PadMode::Reflect,
+    )
+  }
+
   // TODO: some optimization
   fn forward_tile(&self, x: &Tensor, tile_size: usize) -> Result<Tensor, candle_core::Error> {
     let (_, _, h0, w0) = x.shape().dims4()?;
 
-    let h_tiles = (h0 - 1) / tile_size + 1;
-    let w_tiles = (w0 - 1) / tile_size + 1;
+    let x = Self::pad_tile(x, tile_size)?;
 
-    let ph = h_tiles * tile_size;
-    let pw = w_tiles * tile_size;
+    let (stats, cache) = self.tile_stages(&x, tile_size)?;
+    let mut res = self.tile_tail(&x, tile_size, &stats, cache)?;
 
-    let x = x
-      .reflection_pad(3, 18, 18 + pw - w0)?
-      .reflection_pad(2, 18, 18 + ph - h0)?;
+    if res.dim(3)? != w0 * 2 || res.dim(2)? != h0 * 2 {
+      res = res.narrow(3, 0, w0 * 2)?.narrow(2, 0, h0 * 2)?;
+    }
 
-    let (n, c, h, w) = x.shape().dims4()?;
+    Ok(res)
+  }
+
+  /// Runs the first four stages over the tiles of a padded image to collect the SE statistics,
+  /// along with the per-tile cache if it is enabled.
+  fn tile_stages(
+    &self,
+    x: &Tensor,
+    tile_size: usize,
+  ) -> Result<(SeStats, Option<TileCache>), candle_core::Error> {
+    let (n, _, h, w) = x.shape().dims4()?;
+
+    let h_tiles = (h - 36) / tile_size;
+    let w_tiles = (w - 36) / tile_size;
 
     // FIXME: we will have this Vec even if cache disabled
-    let mut cache: Vec<SmallVec<[Tensor; 4]>> =
+    let mut cache: TileCache =
       Vec::with_capacity(if self.use_cache { h_tiles * w_tiles } else { 0 });
 
-    let tile_num: u32 = (h_tiles * w_tiles).try_into()?;
+    let tile_count = h_tiles * w_tiles;
+    let tile_num: u32 = tile_count.try_into()?;
     let tile_num: f64 = tile_num.into();
 
+    let [seblock12, seblock22, seblock23, _] = seblocks(&self.unet1, &self.unet2)?;
+
     // Stage 1
     let mut se_mean0 = Tensor::zeros((n, 64, 1, 1), DType::F32, x.device())?;
 
     for i in (0..(h - 36)).step_by(tile_size) {
       for j in (0..(w - 36)).step_by(tile_size) {
+        self.check_cancelled()?;
+
         let (tmp0, x_crop) = self.unet1.forward_a(&x.i((
           ..,
           ..,
@@ -118,21 +254,22 @@ impl UpCunet2x {
         if self.use_cache {
           cache.push(smallvec![tmp0, x_crop]);
         }
+
+        self.report(1, (i / tile_size) * w_tiles + (j / tile_size), tile_count);
       }
     }
 
     se_mean0 = (se_mean0 / tile_num)?;
+    let se_scale0 = seblock12.excitation(&se_mean0)?;
     tracing::info!("Stage 1 finished");
 
     // Stage 2
     let mut se_mean1 = Tensor::zeros((n, 128, 1, 1), DType::F32, x.device())?;
 
-    let Some(seblock12) = &self.unet1.conv2.seblock else {
-      return Err(candle_core::Error::Msg("`unet1.conv2` has no seblock".to_owned()).bt());
-    };
-
     for i in (0..(h - 36)).step_by(tile_size) {
       for j in (0..(w - 36)).step_by(tile_size) {
+        self.check_cancelled()?;
+
         let idx = (i / tile_size) * w_tiles + (j / tile_size);
 
         let (tmp0, mut x_crop) = if self.use_cache {
@@ -147,7 +284,7 @@ impl UpCunet2x {
           ))?)?
         };
 
-        x_crop = seblock12.forward_mean(&x_crop, &se_mean0)?;
+        x_crop = x_crop.broadcast_mul(&se_scale0)?;
         let opt_unet1 = self.unet1.forward_b(&tmp0, &x_crop)?;
         let (tmp_x1, tmp_x2) = self.unet2.forward_a(&opt_unet1)?;
 
@@ -157,21 +294,22 @@ impl UpCunet2x {
         if self.use_cache {
           cache[idx] = smallvec![opt_unet1, tmp_x1, tmp_x2];
         }
+
+        self.report(2, idx, tile_count);
       }
     }
 
     se_mean1 = (se_mean1 / tile_num)?;
+    let se_scale1 = seblock22.excitation(&se_mean1)?;
     tracing::info!("Stage 2 finished");
 
     // Stage 3
     let mut se_mean2 = Tensor::zeros((n, 128, 1, 1), DType::F32, x.device())?;
 
-    let Some(seblock22) = &self.unet2.conv2.seblock else {
-      return Err(candle_core::Error::Msg("`unet2.conv2` has no seblock".to_owned()).bt());
-    };
-
     for i in (0..(h - 36)).step_by(tile_size) {
       for j in (0..(w - 36)).step_by(tile_size) {
+        self.check_cancelled()?;
+
         let idx = (i / tile_size) * w_tiles + (j / tile_size);
 
         let (opt_unet1, tmp_x1, mut tmp_x2) = if self.use_cache {
@@ -185,14 +323,14 @@ impl UpCunet2x {
             j..(j + tile_size + 36),
           ))?)?;
 
-          x_crop = seblock12.forward_mean(&x_crop, &se_mean0)?;
+          x_crop = x_crop.broadcast_mul(&se_scale0)?;
           let opt_unet1 = self.unet1.forward_b(&tmp0, &x_crop)?;
           let (tmp_x1, tmp_x2) = self.unet2.forward_a(&opt_unet1)?;
 
           (opt_unet1, tmp_x1, tmp_x2)
         };
 
-        tmp_x2 = seblock22.forward_mean(&tmp_x2, &se_mean1)?;
+        tmp_x2 = tmp_x2.broadcast_mul(&se_scale1)?;
         let (tmp_x2, tmp_x3) = self.unet2.forward_b(&tmp_x2)?;
 
         let tmp_se_mean = tmp_x3.mean_keepdim((2, 3))?;
@@ -201,21 +339,22 @@ impl UpCunet2x {
         if self.use_cache {
           cache[idx] = smallvec![opt_unet1, tmp_x1, tmp_x2, tmp_x3];
         }
+
+        self.report(3, idx, tile_count);
       }
     }
 
     se_mean2 = (se_mean2 / tile_num)?;
+    let se_scale2 = seblock23.excitation(&se_mean2)?;
     tracing::info!("Stage 3 finished");
 
     // Stage 4
     let mut se_mean3 = Tensor::zeros((n, 64, 1, 1), DType::F32, x.device())?;
 
-    let Some(seblock23) = &self.unet2.conv3.seblock else {
-      return Err(candle_core::Error::Msg("`unet2.conv3` has no seblock".to_owned()).bt());
-    };
-
     for i in (0..(h - 36)).step_by(tile_size) {
       for j in (0..(w - 36)).step_by(tile_size) {
+        self.check_cancelled()?;
+
         let idx = (i / tile_size) * w_tiles + (j / tile_size);
 
         let (opt_unet1, tmp_x1, tmp_x2, mut tmp_x3) = if self.use_cache {
@@ -234,16 +373,16 @@ impl UpCunet2x {
             j..(j + tile_size + 36),
           ))?)?;
 
-          x_crop = seblock12.forward_mean(&x_crop, &se_mean0)?;
+          x_crop = x_crop.broadcast_mul(&se_scale0)?;
           let opt_unet1 = self.unet1.forward_b(&tmp0, &x_crop)?;
           let (tmp_x1, mut tmp_x2) = self.unet2.forward_a(&opt_unet1)?;
-          tmp_x2 = seblock22.forward_mean(&tmp_x2, &se_mean1)?;
+          tmp_x2 = tmp_x2.broadcast_mul(&se_scale1)?;
           let (tmp_x2, tmp_x3) = self.unet2.forward_b(&tmp_x2)?;
 
           (opt_unet1, tmp_x1, tmp_x2, tmp_x3)
         };
 
-        tmp_x3 = seblock23.forward_mean(&tmp_x3, &se_mean2)?;
+        tmp_x3 = tmp_x3.broadcast_mul(&se_scale2)?;
         let mut tmp_x4 = self.unet2.forward_c(&tmp_x2, &tmp_x3)?;
         tmp_x4 = (tmp_x4 * self.alpha)?;
 
@@ -253,54 +392,60 @@ impl UpCunet2x {
         if self.use_cache {
           cache[idx] = smallvec![opt_unet1, tmp_x1, tmp_x4];
         }
+
+        self.report(4, idx, tile_count);
       }
     }
 
     se_mean3 = (se_mean3 / tile_num)?;
     tracing::info!("Stage 4 finished");
 
-    // Stage tail
+    Ok((
+      SeStats([se_mean0, se_mean1, se_mean2, se_mean3]),
+      self.use_cache.then_some(cache),
+    ))
+  }
+
+  /// Runs the last stage over the tiles of a padded image, reusing the cache from
+  /// [`Self::tile_stages`] when available.
+  fn tile_tail(
+    &self,
+    x: &Tensor,
+    tile_size: usize,
+    stats: &SeStats,
+    cache: Option<TileCache>,
+  ) -> Result<Tensor, candle_core::Error> {
+    let (n, c, h, w) = x.shape().dims4()?;
+
+    let h_tiles = (h - 36) / tile_size;
+    let w_tiles = (w - 36) / tile_size;
+
     let mut res = Tensor::zeros((n, c, h * 2 - 72, w * 2 - 72), DType::F32, x.device())?;
 
-    let Some(seblock24) = &self.unet2.conv4.seblock else {
-      return Err(candle_core::Error::Msg("`unet2.conv4` has no seblock".to_owned()).bt());
-    };
+    let scales = SeScales::new(&self.unet1, &self.unet2, stats)?;
 
     for i in (0..(h - 36)).step_by(tile_size) {
       for j in (0..(w - 36)).step_by(tile_size) {
+        self.check_cancelled()?;
+
         let idx = (i / tile_size) * w_tiles + (j / tile_size);
 
-        let (mut x_crop, tmp_x1, mut tmp_x4) = if self.use_cache {
+        let (x_crop, tmp_x1, tmp_x4) = if let Some(cache) = &cache {
           let res = &cache[idx];
           (res[0].clone(), res[1].clone(), res[2].clone())
         } else {
-          let (tmp0, mut x_crop) = self.unet1.forward_a(&x.i((
-            ..,
-            ..,
-            i..(i + tile_size + 36),
-            j..(j + tile_size + 36),
-          ))?)?;
-
-          x_crop = seblock12.forward_mean(&x_crop, &se_mean0)?;
-          x_crop = self.unet1.forward_b(&tmp0, &x_crop)?;
-          let (tmp_x1, mut tmp_x2) = self.unet2.forward_a(&x_crop)?;
-          tmp_x2 = seblock22.forward_mean(&tmp_x2, &se_mean1)?;
-          let (tmp_x2, mut tmp_x3) = self.unet2.forward_b(&tmp_x2)?;
-          tmp_x3 = seblock23.forward_mean(&tmp_x3, &se_mean2)?;
-          let mut tmp_x4 = self.unet2.forward_c(&tmp_x2, &tmp_x3)?;
-          // TODO: check this
-          tmp_x4 = (tmp_x4 * self.alpha)?;
+          let (x_crop, tmp_x1, tmp_x4, _) = forward_crop_head(
+            &self.unet1,
+            &self.unet2,
+            self.alpha,
+            &x.i((.., .., i..(i + tile_size + 36), j..(j + tile_size + 36)))?,
+            Some(&scales),
+          )?;
 
           (x_crop, tmp_x1, tmp_x4)
         };
 
-        x_crop = x_crop
-          .narrow(3, 20, x_crop.dim(3)? - 40)?
-          .narrow(2, 20, x_crop.dim(2)? - 40)?;
-
-        tmp_x4 = seblock24.forward_mean(&tmp_x4, &se_mean3)?;
-        let x0 = self.unet2.forward_d(&tmp_x1, &tmp_x4)?;
-        x_crop = x0.add(&x_crop)?;
+        let x_crop = forward_crop_tail(&x_crop, &self.unet2, &tmp_x1, &tmp_x4, &scales.0[3])?;
 
         res = res.slice_assign(
           &[
@@ -311,11 +456,9 @@ impl UpCunet2x {
           ],
           &x_crop,
         )?;
-      }
-    }
 
-    if w0 != pw || h0 != ph {
-      res = res.narrow(3, 0, w0 * 2)?.narrow(2, 0, h0 * 2)?;
+        self.report(Progress::STAGE_NUM, idx, h_tiles * w_tiles);
+      }
     }
 
     Ok(res)
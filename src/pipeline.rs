@@ -0,0 +1,214 @@
+use std::slice;
+
+use candle_core::{DType, Device, Tensor};
+use image::DynamicImage;
+use resize::Pixel;
+use rgb::FromSlice;
+
+use crate::{
+  cli::Filter,
+  color::{decode_gamma, encode_gamma},
+  model::{RealCugan, SeStats},
+  utils::{infer, preprocess_alpha_channel, to_network_input},
+};
+
+impl From<Filter> for resize::Type {
+  fn from(filter: Filter) -> Self {
+    match filter {
+      Filter::Point => Self::Point,
+      Filter::Triangle => Self::Triangle,
+      Filter::CatmullRom => Self::Catrom,
+      Filter::Mitchell => Self::Mitchell,
+      Filter::Lanczos3 => Self::Lanczos3,
+    }
+  }
+}
+
+/// Converts an image into a `(height, width, 3)` tensor of `[0, 255]` values.
+///
+/// If `keep_alpha` is set, the colour channels are premultiplied by the alpha channel, which is
+/// returned alongside as raw bytes.
+pub fn image_to_tensor(
+  img: DynamicImage,
+  keep_alpha: bool,
+  device: &Device,
+) -> Result<(Tensor, Option<Vec<u8>>), candle_core::Error> {
+  let width: usize = img.width().try_into()?;
+  let height: usize = img.height().try_into()?;
+
+  if keep_alpha {
+    let data = Tensor::from_vec(img.into_rgba8().into_raw(), (height, width, 4), device)?;
+    let (rgb, alpha) = preprocess_alpha_channel(&data)?;
+    Ok((rgb, Some(alpha)))
+  } else {
+    let rgb = Tensor::from_vec(img.into_rgb8().into_raw(), (height, width, 3), device)?;
+    Ok((rgb.to_dtype(DType::F32)?, None))
+  }
+}
+
+/// Collects the SE statistics of every pass, running all but the last pass on `rgb`.
+pub fn collect_stats(
+  passes: &[&RealCugan],
+  rgb: &Tensor,
+) -> Result<Vec<SeStats>, candle_core::Error> {
+  let mut stats = Vec::with_capacity(passes.len());
+  let mut x = rgb.unsqueeze(0)?;
+
+  for (idx, model) in passes.iter().enumerate() {
+    stats.push(model.se_stats(&to_network_input(&x)?)?);
+    if idx + 1 < passes.len() {
+      x = infer(model, &x, stats.last())?;
+    }
+  }
+
+  Ok(stats)
+}
+
+/// Runs the passes of Real-CUGAN one after another on a `(height, width, 3)` tensor and
+/// resamples the result to the target size.
+///
+/// If `stats` is given, the SE blocks of each pass use it instead of the statistics of their
+/// input. If `linear` is set, the resampling is done in linear light instead of on
+/// gamma-encoded values.
+pub fn upscale(
+  passes: &[&RealCugan],
+  rgb: &Tensor,
+  stats: Option<&[SeStats]>,
+  target_width: usize,
+  target_height: usize,
+  filter: Filter,
+  linear: bool,
+) -> Result<Tensor, candle_core::Error> {
+  let mut res = upscale_batch(
+    passes,
+    slice::from_ref(rgb),
+    stats,
+    target_width,
+    target_height,
+    filter,
+    linear,
+  )?;
+
+  Ok(res.remove(0))
+}
+
+/// Same as [`upscale`], but runs several `(height, width, 3)` tensors of the same size through
+/// each pass at once.
+pub fn upscale_batch(
+  passes: &[&RealCugan],
+  rgbs: &[Tensor],
+  stats: Option<&[SeStats]>,
+  target_width: usize,
+  target_height: usize,
+  filter: Filter,
+  linear: bool,
+) -> Result<Vec<Tensor>, candle_core::Error> {
+  let mut res = Tensor::stack(rgbs, 0)?;
+
+  for (idx, model) in passes.iter().enumerate() {
+    res = infer(model, &res, stats.map(|stats| &stats[idx]))?;
+    tracing::info!(
+      pass = idx + 1,
+      scale = model.scale(),
+      batch = rgbs.len(),
+      "Real-CUGAN finished"
+    );
+  }
+
+  let res = res.round()?;
+
+  (0..rgbs.len())
+    .map(|idx| resample(res.get(idx)?, target_width, target_height, filter, linear))
+    .collect()
+}
+
+/// Resamples a `(height, width, 3)` tensor to the target size.
+pub fn resample(
+  res: Tensor,
+  target_width: usize,
+  target_height: usize,
+  filter: Filter,
+  linear: bool,
+) -> Result<Tensor, candle_core::Error> {
+  let cur_width = res.dim(1)?;
+  let cur_height = res.dim(0)?;
+
+  if cur_width == target_width && cur_height == target_height {
+    tracing::info!("Skip resampling");
+    return Ok(res);
+  }
+
+  let mut resizer = resize::new(
+    cur_width,
+    cur_height,
+    target_width,
+    target_height,
+    Pixel::RGBF32,
+    filter.into(),
+  )
+  .expect("Failed to initialize the target resizer");
+
+  let mut src = res.flatten_all()?.to_vec1()?;
+  let device = res.device().clone();
+  drop(res);
+
+  if linear {
+    decode_gamma(&mut src);
+  }
+
+  let mut dst = vec![0.; target_width * target_height * 3];
+
+  resizer
+    .resize(src.as_rgb(), dst.as_rgb_mut())
+    .expect("Failed to resample the target image");
+
+  if linear {
+    encode_gamma(&mut dst);
+  }
+
+  tracing::info!("Image resample to target");
+
+  Tensor::from_vec(dst, (target_height, target_width, 3), &device)
+}
+
+/// Places raw pixels in the centre of a canvas of the given size, cropping the overflow and
+/// filling the rest with `pad_color`.
+pub fn fit_canvas(
+  buffer: Vec<u8>,
+  channels: usize,
+  (width, height): (usize, usize),
+  (canvas_width, canvas_height): (usize, usize),
+  pad_color: [u8; 4],
+) -> Vec<u8> {
+  if width == canvas_width && height == canvas_height {
+    return buffer;
+  }
+
+  let mut dst: Vec<_> = pad_color[..channels]
+    .iter()
+    .copied()
+    .cycle()
+    .take(canvas_width * canvas_height * channels)
+    .collect();
+
+  // Offsets of the overlapping area in the source and in the canvas
+  let (src_x, dst_x) = (
+    width.saturating_sub(canvas_width) / 2,
+    canvas_width.saturating_sub(width) / 2,
+  );
+  let (src_y, dst_y) = (
+    height.saturating_sub(canvas_height) / 2,
+    canvas_height.saturating_sub(height) / 2,
+  );
+  let row_len = width.min(canvas_width) * channels;
+
+  for y in 0..height.min(canvas_height) {
+    let src = ((src_y + y) * width + src_x) * channels;
+    let dst_pos = ((dst_y + y) * canvas_width + dst_x) * channels;
+    dst[dst_pos..dst_pos + row_len].copy_from_slice(&buffer[src..src + row_len]);
+  }
+
+  tracing::info!(canvas_width, canvas_height, "Image fitted to the canvas");
+
+  dst
+}
@@ -0,0 +1,320 @@
+use candle_core::{DType, Device, Shape, Tensor};
+use candle_nn::{var_builder::SimpleBackend, Init, VarBuilder};
+
+use crate::{
+  cli::{Fit, UpscaleArgs},
+  locate::{locate_model, model_file_name, ModelSource},
+  model::{CancelToken, RealCugan},
+  progress::progress_bar,
+};
+
+/// One network pass of the upscaling chain.
+#[derive(Clone, PartialEq)]
+pub struct Pass {
+  pub scale: usize,
+  pub denoise_level: String,
+  /// Blends the no-denoise and denoise3x models instead of using `denoise_level`.
+  pub denoise_strength: Option<f64>,
+  pub tile_size: Option<usize>,
+  pub quantize: bool,
+}
+
+impl Pass {
+  pub fn load(
+    &self,
+    args: &UpscaleArgs,
+    device: &Device,
+    cancel: Option<&CancelToken>,
+  ) -> Result<Option<RealCugan>, candle_core::Error> {
+    if !matches!(self.scale, 2 | 3) {
+      tracing::error!(scale = self.scale, "Unsupported upscale ratio");
+      return Ok(None);
+    }
+
+    let vb = match self.denoise_strength {
+      Some(strength) => {
+        let Some(light) = locate_model(args, &model_file_name("0", self.scale)) else {
+          return Ok(None);
+        };
+        let Some(strong) = locate_model(args, &model_file_name("3", self.scale)) else {
+          return Ok(None);
+        };
+
+        let vb = blend_weights(&light, &strong, strength, device)?;
+        tracing::info!(strength, "Denoise models blended");
+        vb
+      }
+      None => {
+        let model_name = model_file_name(&self.denoise_level, self.scale);
+
+        let Some(source) = locate_model(args, &model_name) else {
+          return Ok(None);
+        };
+
+        source.var_builder(device)?
+      }
+    };
+
+    let mut model = RealCugan::new(
+      self.scale,
+      args.alpha,
+      self.tile_size,
+      !args.no_cache,
+      self.quantize,
+      vb,
+    )?;
+    model.set_progress(progress_bar());
+    model.set_cancel_token(cancel.cloned());
+
+    Ok(Some(model))
+  }
+}
+
+/// Loads the model of each distinct pass of the plan, returning `None` if one is missing.
+pub fn load_models(
+  plan: &[Pass],
+  args: &UpscaleArgs,
+  device: &Device,
+  cancel: Option<&CancelToken>,
+) -> Result<Option<Vec<(Pass, RealCugan)>>, candle_core::Error> {
+  let mut models: Vec<(Pass, RealCugan)> = vec![];
+
+  for pass in plan {
+    if models.iter().any(|(loaded, _)| loaded == pass) {
+      continue;
+    }
+
+    let Some(model) = pass.load(args, device, cancel)? else {
+      return Ok(None);
+    };
+    models.push((pass.clone(), model));
+  }
+
+  Ok(Some(models))
+}
+
+/// Returns the model to run for each pass of the plan.
+pub fn chain_models<'a>(plan: &[Pass], models: &'a [(Pass, RealCugan)]) -> Vec<&'a RealCugan> {
+  plan
+    .iter()
+    .map(|pass| {
+      models
+        .iter()
+        .find(|(loaded, _)| loaded == pass)
+        .map(|(_, model)| model)
+        .expect("Every pass of the plan has a model")
+    })
+    .collect()
+}
+
+/// Interpolates the weights of two models of the same architecture, `strength` being the weight
+/// of `strong`.
+struct Blend {
+  light: VarBuilder<'static>,
+  strong: VarBuilder<'static>,
+  strength: f64,
+}
+
+impl Blend {
+  fn mix(&self, light: Tensor, strong: Tensor, dtype: DType) -> Result<Tensor, candle_core::Error> {
+    let light = light.to_dtype(DType::F32)?;
+    let strong = strong.to_dtype(DType::F32)?;
+
+    ((light * (1. - self.strength))? + (strong * self.strength)?)?.to_dtype(dtype)
+  }
+}
+
+impl SimpleBackend for Blend {
+  fn get(
+    &self,
+    s: Shape,
+    name: &str,
+    _: Init,
+    dtype: DType,
+    _: &Device,
+  ) -> Result<Tensor, candle_core::Error> {
+    let light = self.light.get(s.clone(), name)?;
+    let strong = self.strong.get(s, name)?;
+
+    self.mix(light, strong, dtype)
+  }
+
+  fn get_unchecked(
+    &self,
+    name: &str,
+    dtype: DType,
+    _: &Device,
+  ) -> Result<Tensor, candle_core::Error> {
+    let light = self.light.get_unchecked_dtype(name, DType::F32)?;
+    let strong = self.strong.get_unchecked_dtype(name, DType::F32)?;
+
+    self.mix(light, strong, dtype)
+  }
+
+  fn contains_tensor(&self, name: &str) -> bool {
+    self.light.contains_tensor(name) && self.strong.contains_tensor(name)
+  }
+}
+
+/// Loads both models like a single one and blends each tensor as the network asks for it.
+fn blend_weights(
+  light: &ModelSource,
+  strong: &ModelSource,
+  strength: f64,
+  device: &Device,
+) -> Result<VarBuilder<'static>, candle_core::Error> {
+  let blend = Blend {
+    light: light.var_builder(device)?,
+    strong: strong.var_builder(device)?,
+    strength,
+  };
+
+  Ok(VarBuilder::from_backend(
+    Box::new(blend),
+    DType::F32,
+    device.clone(),
+  ))
+}
+
+/// Returns the network passes to run on an image of the given size.
+///
+/// Without `--tile-size`, the passes after the first one are tiled so that no tile is larger
+/// than the input of the first pass, which keeps the memory usage close to a single pass.
+pub fn plan_passes(args: &UpscaleArgs, width: usize, height: usize) -> Vec<Pass> {
+  let scales: Vec<(usize, Option<&str>)> = if !args.passes.is_empty() {
+    args
+      .passes
+      .iter()
+      .map(|spec| (spec.scale.into(), spec.denoise_level.as_deref()))
+      .collect()
+  } else if args.auto_scale {
+    auto_scales(args, width, height)
+      .into_iter()
+      .map(|scale| (scale, None))
+      .collect()
+  } else {
+    vec![(args.scale.into(), None)]
+  };
+
+  // Tiles are padded to a multiple of 4 to suit both networks
+  let max_tile = (width.max(height) - 1) / 4 * 4 + 4;
+
+  scales
+    .into_iter()
+    .enumerate()
+    .map(|(idx, (scale, denoise_level))| {
+      let tile_size = match args.tile_size {
+        Some(tile_size) => Some(tile_size),
+        None if idx > 0 => Some(max_tile),
+        None => None,
+      };
+
+      // An explicit denoise level of the pass takes precedence over `--denoise-strength`
+      let denoise_strength = match denoise_level {
+        Some(_) => None,
+        None => args.denoise_strength,
+      };
+
+      let pass = Pass {
+        scale,
+        denoise_level: denoise_level.unwrap_or(&args.denoise_level).to_owned(),
+        denoise_strength,
+        tile_size,
+        quantize: args.quantize,
+      };
+
+      tracing::info!(
+        pass = idx + 1,
+        scale,
+        denoise_level = pass.denoise_level,
+        denoise_strength,
+        tile_size,
+        "Pass planned",
+      );
+
+      pass
+    })
+    .collect()
+}
+
+/// Returns the smallest chain of 2x and 3x passes that reaches the target size of
+/// `--auto-scale`, which is then downsampled to the exact size.
+fn auto_scales(args: &UpscaleArgs, width: usize, height: usize) -> Vec<usize> {
+  // `--auto-scale` requires a target width or height, so the scale here is never used
+  let ((target_width, target_height), _) = target_size(args, width, height, 1);
+  let ratio = f64::max(
+    target_width as f64 / width as f64,
+    target_height as f64 / height as f64,
+  );
+
+  // Every total scale is of the form `3^threes * 2^twos`, take the smallest one that reaches
+  // the ratio
+  let mut plan = vec![];
+  let mut best = usize::MAX;
+
+  for threes in 0.. {
+    let base = 3usize.pow(threes);
+
+    let mut total = base;
+    let mut twos = 0;
+    while (total as f64) < ratio || threes + twos == 0 {
+      total *= 2;
+      twos += 1;
+    }
+
+    if total < best {
+      best = total;
+      plan = [vec![3; threes as usize], vec![2; twos as usize]].concat();
+    }
+
+    if base as f64 >= ratio {
+      break;
+    }
+  }
+
+  tracing::info!(ratio, ?plan, "Scale plan chosen");
+
+  plan
+}
+
+/// Returns the size to resample the result to, and the size of the final canvas.
+pub fn target_size(
+  args: &UpscaleArgs,
+  width: usize,
+  height: usize,
+  scale: usize,
+) -> ((usize, usize), (usize, usize)) {
+  let size = match (args.width, args.height) {
+    (Some(w), Some(h)) => {
+      let scale_x = w as f64 / width as f64;
+      let scale_y = h as f64 / height as f64;
+
+      let scale = match args.fit {
+        Fit::Stretch => return ((w, h), (w, h)),
+        Fit::Contain | Fit::Max => scale_x.min(scale_y),
+        Fit::Cover => scale_x.max(scale_y),
+      };
+
+      let size = (
+        ((width as f64 * scale).round() as usize).max(1),
+        ((height as f64 * scale).round() as usize).max(1),
+      );
+
+      return match args.fit {
+        Fit::Max => (size, size),
+        _ => (size, (w, h)),
+      };
+    }
+    (Some(w), None) => {
+      let h = (w * height) as f64 / width as f64;
+      (w, h.round() as usize)
+    }
+    (None, Some(h)) => {
+      let w = (h * width) as f64 / height as f64;
+      (w.round() as usize, h)
+    }
+    _ => (width * scale, height * scale),
+  };
+
+  (size, size)
+}
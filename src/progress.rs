@@ -0,0 +1,85 @@
+use std::{
+  io::{self, IsTerminal, Write},
+  sync::Mutex,
+  time::Duration,
+};
+
+use indicatif::{ProgressBar, ProgressStyle};
+use real_cugan_rs::model::{Progress, ProgressFn};
+
+/// The bar of the running tiled pass, if any.
+static ACTIVE_BAR: Mutex<Option<ProgressBar>> = Mutex::new(None);
+
+/// Returns a callback drawing the progress of tiled inference on stderr, or `None` if stderr is
+/// not a terminal.
+///
+/// Each pass gets its own bar, which covers the stages left to run and disappears once the last
+/// stage is done.
+pub fn progress_bar() -> Option<ProgressFn> {
+  if !io::stderr().is_terminal() {
+    return None;
+  }
+
+  Some(Box::new(|progress: Progress| {
+    let mut active = ACTIVE_BAR
+      .lock()
+      .expect("The progress bar lock is poisoned");
+
+    // Stages left after the current one
+    let stages_left = Progress::STAGE_NUM - progress.stage;
+
+    let bar = active.get_or_insert_with(|| {
+      let len = (stages_left + 1) * progress.tile_num;
+      let bar = ProgressBar::new(len.try_into().expect("The tile count fits in u64"));
+      bar.set_style(
+        ProgressStyle::with_template("{msg} [{bar:40}] {pos}/{len} tiles, ETA {eta}")
+          .expect("The progress bar template is valid")
+          .progress_chars("=> "),
+      );
+      bar.enable_steady_tick(Duration::from_secs(1));
+      bar
+    });
+
+    let remaining = stages_left * progress.tile_num + progress.tile_num - progress.tile;
+    bar.set_position(bar.length().unwrap_or_default() - remaining as u64);
+    bar.set_message(format!("Stage {}/{}", progress.stage, Progress::STAGE_NUM));
+
+    if remaining == 0 {
+      bar.finish_and_clear();
+      *active = None;
+    }
+  }))
+}
+
+/// Removes the bar of a pass that stopped before its last tile.
+pub fn clear_progress_bar() {
+  let bar = ACTIVE_BAR
+    .lock()
+    .expect("The progress bar lock is poisoned")
+    .take();
+
+  if let Some(bar) = bar {
+    bar.finish_and_clear();
+  }
+}
+
+/// Writes the logs to stdout, hiding the progress bar while doing so.
+pub struct LogWriter;
+
+impl Write for LogWriter {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    let bar = ACTIVE_BAR
+      .lock()
+      .expect("The progress bar lock is poisoned")
+      .clone();
+
+    match bar {
+      Some(bar) => bar.suspend(|| io::stdout().write(buf)),
+      None => io::stdout().write(buf),
+    }
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    io::stdout().flush()
+  }
+}
@@ -0,0 +1,211 @@
+//! The `real_cugan_rs` Python module, built with `maturin build --features python`.
+
+use candle_core::{DType, Device, Tensor};
+use candle_nn::VarBuilder;
+use numpy::{ndarray::Array3, IntoPyArray, PyArray3, PyReadonlyArray3, PyUntypedArrayMethods};
+use pyo3::{
+  exceptions::{PyRuntimeError, PyValueError},
+  prelude::*,
+};
+use resize::Pixel;
+use rgb::FromSlice;
+
+use crate::{
+  model::RealCugan,
+  utils::{infer, upscale_pixels},
+};
+
+fn runtime_error(err: candle_core::Error) -> PyErr {
+  PyRuntimeError::new_err(err.to_string())
+}
+
+/// Upscales images given as `numpy.ndarray` with a loaded Real-CUGAN model.
+#[pyclass(frozen)]
+struct Upscaler {
+  model: RealCugan,
+  device: Device,
+}
+
+#[pymethods]
+impl Upscaler {
+  /// Loads the `.pth` or `.safetensors` model at `model_path`, whose scale must be `scale`.
+  ///
+  /// `tile_size`, `no_cache`, `quantize` and `alpha` work like the options of the CLI.
+  #[new]
+  #[pyo3(signature = (
+    model_path,
+    scale = 2,
+    *,
+    alpha = 1.0,
+    tile_size = None,
+    no_cache = false,
+    use_cpu = true,
+    quantize = false,
+  ))]
+  fn new(
+    model_path: &str,
+    scale: usize,
+    alpha: f64,
+    tile_size: Option<usize>,
+    no_cache: bool,
+    use_cpu: bool,
+    quantize: bool,
+  ) -> PyResult<Self> {
+    if !matches!(scale, 2 | 3) {
+      return Err(PyValueError::new_err(format!(
+        "unsupported upscale ratio {scale}"
+      )));
+    }
+    if tile_size == Some(0) {
+      return Err(PyValueError::new_err("the tile size must be positive"));
+    }
+
+    let load = || -> Result<Self, candle_core::Error> {
+      let device = if use_cpu {
+        Device::Cpu
+      } else {
+        Device::new_cuda(0)?
+      };
+
+      let vb = if model_path.ends_with(".safetensors") {
+        let tensors = candle_core::safetensors::load(model_path, &device)?;
+        VarBuilder::from_tensors(tensors, DType::F32, &device)
+      } else {
+        VarBuilder::from_pth(model_path, DType::F32, &device)?
+      };
+
+      let model = RealCugan::new(scale, alpha, tile_size, !no_cache, quantize, vb)?;
+      Ok(Self { model, device })
+    };
+
+    load().map_err(|err| {
+      PyRuntimeError::new_err(format!("Failed to load the model {model_path:?}: {err}"))
+    })
+  }
+
+  /// The upscale ratio of the model.
+  #[getter]
+  fn scale(&self) -> usize {
+    self.model.scale()
+  }
+
+  /// Upscales an `(height, width, 3 | 4)` array of `uint8` in `[0, 255]` or `float32` in
+  /// `[0, 1]`, returning an array of the same type.
+  ///
+  /// The colours of RGBA images are not premultiplied by their alpha.
+  fn upscale<'py>(
+    &self,
+    py: Python<'py>,
+    image: &Bound<'py, PyAny>,
+  ) -> PyResult<Bound<'py, PyAny>> {
+    if let Ok(image) = image.extract::<PyReadonlyArray3<u8>>() {
+      let (height, width, channels) = dims(image.shape())?;
+      let pixels: Vec<u8> = image.as_array().iter().copied().collect();
+
+      let (buffer, width, height) = py
+        .detach(|| {
+          let data = Tensor::from_vec(pixels, (height, width, channels), &self.device)?;
+          upscale_pixels(&self.model, &data)
+        })
+        .map_err(runtime_error)?;
+
+      return Ok(to_array(py, buffer, height, width, channels)?.into_any());
+    }
+
+    if let Ok(image) = image.extract::<PyReadonlyArray3<f32>>() {
+      let (height, width, channels) = dims(image.shape())?;
+      let pixels: Vec<f32> = image.as_array().iter().copied().collect();
+
+      let (buffer, width, height) = py
+        .detach(|| self.upscale_f32(pixels, width, height, channels))
+        .map_err(runtime_error)?;
+
+      return Ok(to_array(py, buffer, height, width, channels)?.into_any());
+    }
+
+    Err(PyValueError::new_err(
+      "the image must be a 3-dimensional array of uint8 or float32",
+    ))
+  }
+}
+
+impl Upscaler {
+  /// Same as [`upscale_pixels`] on `[0, 1]` values, keeping the full precision of the network.
+  fn upscale_f32(
+    &self,
+    pixels: Vec<f32>,
+    width: usize,
+    height: usize,
+    channels: usize,
+  ) -> Result<(Vec<f32>, usize, usize), candle_core::Error> {
+    let data = Tensor::from_vec(pixels, (height, width, channels), &self.device)?;
+    let rgb = (data.narrow(2, 0, 3)? * 255.)?;
+
+    let (rgb, alpha) = if channels == 4 {
+      let alpha = data.narrow(2, 3, 1)?;
+      (rgb.broadcast_mul(&alpha)?, Some(alpha))
+    } else {
+      (rgb, None)
+    };
+
+    let res = (infer(&self.model, &rgb.unsqueeze(0)?, None)?.squeeze(0)? / 255.)?;
+    let (target_height, target_width, _) = res.dims3()?;
+
+    let res = match alpha {
+      Some(alpha) => {
+        let alpha: Vec<f32> = alpha.flatten_all()?.to_vec1()?;
+        let mut resizer = resize::new(
+          width,
+          height,
+          target_width,
+          target_height,
+          Pixel::GrayF32,
+          resize::Type::Mitchell,
+        )
+        .expect("Failed to initialize the alpha channel resizer");
+
+        let mut dst = vec![0.; target_width * target_height];
+        resizer
+          .resize(alpha.as_gray(), dst.as_gray_mut())
+          .expect("Failed to upscale the alpha channel");
+
+        let alpha =
+          Tensor::from_vec(dst, (target_height, target_width, 1), &self.device)?.clamp(0., 1.)?;
+        // Transparent pixels have no colour to restore
+        let rgb = res.broadcast_div(&alpha.maximum(1e-6)?)?;
+        Tensor::cat(&[rgb, alpha], 2)?
+      }
+      None => res,
+    };
+
+    let buffer = res.clamp(0., 1.)?.flatten_all()?.to_vec1()?;
+    Ok((buffer, target_width, target_height))
+  }
+}
+
+/// Returns the size of an image array, which must have 3 or 4 channels.
+fn dims(shape: &[usize]) -> PyResult<(usize, usize, usize)> {
+  match *shape {
+    [height, width, channels @ (3 | 4)] if height > 0 && width > 0 => Ok((height, width, channels)),
+    _ => Err(PyValueError::new_err(format!(
+      "the image must have the shape (height, width, 3 | 4), not {shape:?}"
+    ))),
+  }
+}
+
+fn to_array<T: numpy::Element>(
+  py: Python<'_>,
+  buffer: Vec<T>,
+  height: usize,
+  width: usize,
+  channels: usize,
+) -> PyResult<Bound<'_, PyArray3<T>>> {
+  let array = Array3::from_shape_vec((height, width, channels), buffer)
+    .map_err(|err| PyRuntimeError::new_err(err.to_string()))?;
+  Ok(array.into_pyarray(py))
+}
+
+#[pymodule]
+fn real_cugan_rs(m: &Bound<'_, PyModule>) -> PyResult<()> {
+  m.add_class::<Upscaler>()
+}
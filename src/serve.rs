@@ -0,0 +1,490 @@
+use std::{
+  fs,
+  io::{Cursor, Read},
+  path::PathBuf,
+  sync::{
+    mpsc::{self, TrySendError},
+    Arc, Mutex,
+  },
+  thread,
+};
+
+use image::ImageFormat;
+use tempfile::TempDir;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::{
+  cli::ServeArgs,
+  upscaler::{JobError, JobOptions, Upscaler},
+};
+
+/// The formats `save_image` can write.
+const OUTPUT_FORMATS: [ImageFormat; 5] = [
+  ImageFormat::Bmp,
+  ImageFormat::Gif,
+  ImageFormat::Jpeg,
+  ImageFormat::Png,
+  ImageFormat::WebP,
+];
+
+/// The options of an upscaling request, given in its query string.
+struct Params {
+  options: JobOptions,
+  /// Keeps the format of the upload if not given.
+  format: Option<ImageFormat>,
+}
+
+impl Params {
+  /// Parses a query string like `scale=3&denoise=-1&tile=256`, the options it omits being the
+  /// defaults of the server.
+  fn parse(defaults: &JobOptions, query: &str) -> Result<Self, String> {
+    let mut options = defaults.clone();
+    let mut format = None;
+
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+      let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+      let invalid = || format!("invalid value of `{key}`: {value:?}");
+
+      match key {
+        "scale" => match value.parse() {
+          Ok(scale @ (2 | 3)) => options.scale = scale,
+          _ => return Err(invalid()),
+        },
+        "denoise" => match value {
+          "-1" | "0" | "3" => options.denoise_level = value.to_owned(),
+          _ => return Err(invalid()),
+        },
+        "alpha" => match value.parse::<f64>() {
+          Ok(alpha) if alpha.is_finite() => options.alpha = alpha,
+          _ => return Err(invalid()),
+        },
+        "tile" => match value.parse() {
+          Ok(tile_size) if tile_size > 0 => options.tile_size = Some(tile_size),
+          _ => return Err(invalid()),
+        },
+        "format" => match ImageFormat::from_extension(value) {
+          Some(value) if OUTPUT_FORMATS.contains(&value) => format = Some(value),
+          _ => return Err(invalid()),
+        },
+        "lossless" => match value {
+          "" | "1" | "true" => options.lossless = true,
+          "0" | "false" => options.lossless = false,
+          _ => return Err(invalid()),
+        },
+        _ => return Err(format!("unknown parameter `{key}`")),
+      }
+    }
+
+    Ok(Self { options, format })
+  }
+}
+
+/// What the workers share.
+struct State {
+  upscaler: Upscaler,
+  /// The options of the requests that do not give them.
+  defaults: JobOptions,
+  /// In bytes.
+  max_upload: u64,
+  /// Holds the files of the requests, only accessible to the user of the server.
+  temp_dir: TempDir,
+}
+
+/// A request that failed, answered with the status code and the message.
+struct Rejection(u16, String);
+
+impl From<JobError> for Rejection {
+  fn from(err: JobError) -> Self {
+    match err {
+      JobError::Invalid(message) => Rejection(400, message),
+      JobError::Failed(err) => Rejection(500, err.to_string()),
+    }
+  }
+}
+
+/// Files of a request in the temporary directory, removed once the request is answered.
+struct TempFiles(Vec<PathBuf>);
+
+impl Drop for TempFiles {
+  fn drop(&mut self) {
+    for path in &self.0 {
+      let _ = fs::remove_file(path);
+    }
+  }
+}
+
+/// Listens for `POST /upscale` requests, whose body is the image to upscale, and answers them
+/// with the encoded result.
+///
+/// Up to `--workers` requests are upscaled at the same time and up to `--queue-size` more wait
+/// for a worker, the others being refused with `503 Service Unavailable`. Uploads larger than
+/// `--max-upload` are refused with `413 Payload Too Large`.
+pub fn serve(args: ServeArgs) -> Result<(), candle_core::Error> {
+  let temp_dir = match private_temp_dir() {
+    Ok(temp_dir) => temp_dir,
+    Err(err) => {
+      tracing::error!("Failed to create a temporary directory: {err}");
+      return Ok(());
+    }
+  };
+
+  let state = Arc::new(State {
+    upscaler: Upscaler::new(args.models, None)?,
+    defaults: JobOptions {
+      alpha: args.alpha,
+      tile_size: args.tile_size,
+      ..JobOptions::default()
+    },
+    max_upload: u64::try_from(args.max_upload)
+      .unwrap_or(u64::MAX)
+      .saturating_mul(1 << 20),
+    temp_dir,
+  });
+
+  for spec in &args.preload {
+    let mut options = state.defaults.clone();
+    options.scale = spec.scale;
+    if let Some(denoise_level) = &spec.denoise_level {
+      options.denoise_level.clone_from(denoise_level);
+    }
+
+    if !state.upscaler.preload(&options)? {
+      return Ok(());
+    }
+  }
+
+  let server = match Server::http(&args.listen) {
+    Ok(server) => server,
+    Err(err) => {
+      tracing::error!(listen = args.listen, "Failed to listen: {err}");
+      return Ok(());
+    }
+  };
+
+  tracing::info!(
+    listen = args.listen,
+    workers = args.workers,
+    "Server started"
+  );
+
+  run(&server, &state, args.workers, args.queue_size);
+
+  Ok(())
+}
+
+/// Answers the requests of `server` with `workers` threads until it stops listening.
+fn run(server: &Server, state: &Arc<State>, workers: usize, queue_size: usize) {
+  let (sender, receiver) = mpsc::sync_channel::<(usize, Request)>(queue_size);
+  let receiver = Arc::new(Mutex::new(receiver));
+
+  for _ in 0..workers {
+    let receiver = receiver.clone();
+    let state = state.clone();
+
+    thread::spawn(move || loop {
+      let job = receiver.lock().expect("The queue lock is poisoned").recv();
+      let Ok((id, request)) = job else {
+        break;
+      };
+
+      answer(&state, id, request);
+    });
+  }
+
+  for (id, request) in server.incoming_requests().enumerate() {
+    let path = request.url().split('?').next().unwrap_or_default();
+
+    if path != "/upscale" {
+      respond(request, text_response(404, "Not found"));
+      continue;
+    }
+
+    if *request.method() != Method::Post {
+      respond(request, text_response(405, "Use POST to upload the image"));
+      continue;
+    }
+
+    match sender.try_send((id, request)) {
+      Ok(()) => {}
+      Err(TrySendError::Full((_, request))) => {
+        tracing::warn!(id, "Queue full, request refused");
+        respond(request, text_response(503, "Too many requests are waiting"));
+      }
+      // Every worker panicked
+      Err(TrySendError::Disconnected((_, request))) => {
+        tracing::error!(id, "No worker left, request refused");
+        respond(request, text_response(503, "No worker is available"));
+      }
+    }
+  }
+}
+
+/// Upscales the image of the request and answers it.
+fn answer(state: &State, id: usize, mut request: Request) {
+  let _span = tracing::info_span!("request", id).entered();
+  tracing::info!(url = request.url(), "Request started");
+
+  let response = match upscale_request(state, id, &mut request) {
+    Ok((data, format)) => {
+      tracing::info!(size = data.len(), "Request done");
+
+      let content_type = Header::from_bytes("Content-Type", format.to_mime_type())
+        .expect("The content type is a valid header");
+      Response::from_data(data).with_header(content_type)
+    }
+    Err(Rejection(status, message)) => {
+      tracing::error!(status, "{message}");
+      text_response(status, &message)
+    }
+  };
+
+  respond(request, response);
+}
+
+fn upscale_request(
+  state: &State,
+  id: usize,
+  request: &mut Request,
+) -> Result<(Vec<u8>, ImageFormat), Rejection> {
+  let query = request.url().split_once('?').map_or("", |(_, query)| query);
+  let params = Params::parse(&state.defaults, query).map_err(|err| Rejection(400, err))?;
+
+  let too_large = || {
+    Rejection(
+      413,
+      format!("The upload is larger than {} bytes", state.max_upload),
+    )
+  };
+
+  if request
+    .body_length()
+    .is_some_and(|len| len as u64 > state.max_upload)
+  {
+    return Err(too_large());
+  }
+
+  // The body may be longer than announced, or sent in chunks without a length
+  let mut data = vec![];
+  request
+    .as_reader()
+    .take(state.max_upload.saturating_add(1))
+    .read_to_end(&mut data)
+    .map_err(|err| Rejection(400, format!("Failed to read the upload: {err}")))?;
+
+  if data.len() as u64 > state.max_upload {
+    return Err(too_large());
+  }
+
+  let input_format = image::guess_format(&data)
+    .ok()
+    .filter(|format| OUTPUT_FORMATS.contains(format))
+    .ok_or_else(|| Rejection(415, "Unsupported image format".to_owned()))?;
+  let output_format = params.format.unwrap_or(input_format);
+
+  // The pipeline reads and writes files, whose extensions give their formats
+  let temp_path = |role: &str, format: ImageFormat| {
+    state
+      .temp_dir
+      .path()
+      .join(format!("{id}-{role}.{}", format.extensions_str()[0]))
+  };
+  let input_path = temp_path("input", input_format);
+  let output_path = temp_path("output", output_format);
+  let _files = TempFiles(vec![input_path.clone(), output_path.clone()]);
+
+  fs::write(&input_path, &data).map_err(|err| {
+    Rejection(
+      500,
+      format!("Failed to write the upload into a temporary file: {err}"),
+    )
+  })?;
+  drop(data);
+
+  state
+    .upscaler
+    .upscale(&params.options, &input_path, &output_path, output_format)?;
+
+  let data = fs::read(&output_path)
+    .map_err(|err| Rejection(500, format!("Failed to read the upscaled image: {err}")))?;
+  Ok((data, output_format))
+}
+
+/// Creates a directory with a random name in the temporary directory, which other users can
+/// neither list nor write into, so that they cannot plant links in place of the files of the
+/// requests.
+fn private_temp_dir() -> std::io::Result<TempDir> {
+  let mut builder = tempfile::Builder::new();
+  builder.prefix("real-cugan-rs-");
+
+  #[cfg(unix)]
+  {
+    use std::os::unix::fs::PermissionsExt;
+    builder.permissions(fs::Permissions::from_mode(0o700));
+  }
+
+  builder.tempdir()
+}
+
+fn text_response(status: u16, message: &str) -> Response<Cursor<Vec<u8>>> {
+  Response::from_string(message).with_status_code(status)
+}
+
+fn respond(request: Request, response: Response<Cursor<Vec<u8>>>) {
+  if let Err(err) = request.respond(response) {
+    tracing::warn!("Failed to send the response: {err}");
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpStream},
+    sync::Arc,
+    thread,
+  };
+
+  use candle_core::{DType, Device};
+  use candle_nn::{VarBuilder, VarMap};
+  use image::{codecs::png::PngEncoder, ImageEncoder, RgbImage};
+  use tiny_http::Server;
+
+  use super::{private_temp_dir, run, State};
+  use crate::{
+    cli::ModelArgs,
+    upscaler::{JobOptions, Upscaler},
+  };
+
+  const MAX_UPLOAD: u64 = 4096;
+
+  /// Serves with a 2x model of random weights on a free local port until the server is unblocked.
+  fn start() -> (Arc<Server>, SocketAddr, thread::JoinHandle<()>) {
+    let upscaler = Upscaler::new(
+      ModelArgs {
+        model_dir: None,
+        max_models: 1,
+        no_cache: false,
+        use_cpu: true,
+        quantize: false,
+      },
+      None,
+    )
+    .unwrap();
+
+    let defaults = JobOptions::default();
+    let varmap = VarMap::new();
+    let vb = VarBuilder::from_varmap(&varmap, DType::F32, &Device::Cpu);
+    upscaler.insert_model(&defaults, vb).unwrap();
+
+    let state = Arc::new(State {
+      upscaler,
+      defaults,
+      max_upload: MAX_UPLOAD,
+      temp_dir: private_temp_dir().unwrap(),
+    });
+
+    let server = Arc::new(Server::http("127.0.0.1:0").unwrap());
+    let addr = server.server_addr().to_ip().unwrap();
+    let handle = thread::spawn({
+      let server = server.clone();
+      move || run(&server, &state, 1, 4)
+    });
+
+    (server, addr, handle)
+  }
+
+  /// Sends `POST /upscale` with the given headers and body, and returns the status code and the
+  /// body of the response.
+  ///
+  /// The response is read up to its length, since the server may keep the connection open while
+  /// it discards an upload it refused.
+  fn post(addr: SocketAddr, headers: &str, body: &[u8]) -> (u16, Vec<u8>) {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(
+      stream,
+      "POST /upscale HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n{headers}\r\n"
+    )
+    .unwrap();
+    stream.write_all(body).unwrap();
+
+    let mut response = vec![];
+    let mut byte = [0];
+    while !response.ends_with(b"\r\n\r\n") {
+      stream.read_exact(&mut byte).unwrap();
+      response.push(byte[0]);
+    }
+
+    let head = String::from_utf8(response).unwrap();
+    let status = head[9..12].parse().unwrap();
+    let len = head
+      .lines()
+      .find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name
+          .eq_ignore_ascii_case("content-length")
+          .then(|| value.trim().parse().unwrap())
+      })
+      .unwrap();
+
+    let mut body = vec![0; len];
+    stream.read_exact(&mut body).unwrap();
+    (status, body)
+  }
+
+  #[test]
+  fn upload_is_upscaled() {
+    let (server, addr, handle) = start();
+
+    let img = RgbImage::from_fn(24, 16, |x, y| image::Rgb([x as u8 * 10, y as u8 * 15, 90]));
+    let mut data = vec![];
+    PngEncoder::new(&mut data)
+      .write_image(&img, img.width(), img.height(), image::ColorType::Rgb8)
+      .unwrap();
+
+    let (status, body) = post(addr, &format!("Content-Length: {}\r\n", data.len()), &data);
+    assert_eq!(status, 200, "{}", String::from_utf8_lossy(&body));
+
+    let res = image::load_from_memory(&body).unwrap();
+    assert_eq!((res.width(), res.height()), (48, 32));
+
+    let (status, _) = post(addr, "Content-Length: 3\r\n", b"abc");
+    assert_eq!(status, 415);
+
+    server.unblock();
+    handle.join().unwrap();
+  }
+
+  #[test]
+  fn large_uploads_are_refused() {
+    let (server, addr, handle) = start();
+
+    // Refused from the announced length, before the body is sent
+    let (status, _) = post(
+      addr,
+      &format!("Content-Length: {}\r\n", MAX_UPLOAD + 1),
+      b"",
+    );
+    assert_eq!(status, 413);
+
+    // Refused once the chunks pass the limit
+    let chunk = vec![0; MAX_UPLOAD as usize + 1];
+    let mut body = format!("{:x}\r\n", chunk.len()).into_bytes();
+    body.extend_from_slice(&chunk);
+    body.extend_from_slice(b"\r\n0\r\n\r\n");
+    let (status, _) = post(addr, "Transfer-Encoding: chunked\r\n", &body);
+    assert_eq!(status, 413);
+
+    server.unblock();
+    handle.join().unwrap();
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn temp_dir_is_private() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = private_temp_dir().unwrap();
+    let mode = std::fs::metadata(dir.path()).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o700);
+  }
+}
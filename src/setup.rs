@@ -1,14 +1,24 @@
+use std::{io, path::Path};
+
 use image::ImageFormat;
 
 use tracing::Level;
-use tracing_subscriber::FmtSubscriber;
+use tracing_subscriber::{fmt::writer::BoxMakeWriter, FmtSubscriber};
+
+use crate::{cli::UpscaleArgs, progress::LogWriter};
 
-use crate::cli::Cli;
+/// Logs to stdout, or to stderr if stdout carries the output of the worker mode.
+pub fn setup_tracing(to_stderr: bool) {
+  let writer = if to_stderr {
+    BoxMakeWriter::new(io::stderr)
+  } else {
+    BoxMakeWriter::new(|| LogWriter)
+  };
 
-pub fn setup_tracing() {
   let subscriber = FmtSubscriber::builder()
     .with_max_level(Level::INFO)
     .with_target(false)
+    .with_writer(writer)
     .finish();
 
   tracing::subscriber::set_global_default(subscriber).expect("Setting default subscriber failed");
@@ -17,16 +27,38 @@ pub fn setup_tracing() {
   tracing::info!("{STARTUP_INFO}");
 }
 
-pub fn setup_args(args: &Cli) -> Result<ImageFormat, &'static str> {
+/// Checks the arguments, returning the output format, or `None` if the input is a directory and
+/// each output keeps the format of its input.
+pub fn setup_args(args: &UpscaleArgs) -> Result<Option<ImageFormat>, &'static str> {
   if args.no_cache && args.tile_size.is_none() {
     tracing::warn!("Cache only works with tile mode! Ignoring `--no-cache`...");
   }
 
-  let Ok(output_format) = ImageFormat::from_path(&args.output_path) else {
+  if args.auto_scale && args.width.is_none() && args.height.is_none() {
+    return Err("`--auto-scale` requires `--width` or `--height`");
+  }
+
+  if args.input_path.is_dir() {
+    if args.output_path.is_file() {
+      return Err("The output path must be a directory when the input is a directory");
+    }
+
+    if args.quantize_report {
+      tracing::warn!("The quantisation report is only available for a single image");
+    }
+
+    return Ok(None);
+  }
+
+  output_format(&args.output_path, args.lossless).map(Some)
+}
+
+pub fn output_format(path: &Path, lossless: bool) -> Result<ImageFormat, &'static str> {
+  let Ok(output_format) = ImageFormat::from_path(path) else {
     return Err("Failed to get image format from the output path");
   };
 
-  if output_format == ImageFormat::Jpeg && args.lossless {
+  if output_format == ImageFormat::Jpeg && lossless {
     return Err("JPEG images cannot be lossless");
   }
 
@@ -0,0 +1,196 @@
+use std::{fs, path::Path};
+
+use candle_core::{Device, Tensor};
+use image::{io::Reader as ImageReader, DynamicImage, ImageFormat};
+
+use crate::{
+  cli::{Filter, UpscaleArgs},
+  color::SourceProfile,
+  metadata::Metadata,
+  pipeline::{fit_canvas, image_to_tensor},
+  utils::{resize_alpha, save_image, tensor_to_buffer},
+};
+
+/// What is known about an input file before decoding it.
+pub struct Source {
+  pub metadata: Metadata,
+  pub profile: Option<SourceProfile>,
+  /// Size of the image once turned upright
+  pub width: usize,
+  pub height: usize,
+}
+
+impl Source {
+  pub fn read(args: &UpscaleArgs, path: &Path) -> Result<Self, candle_core::Error> {
+    let metadata = Metadata::read(&fs::read(path).expect("Failed to read image file"));
+
+    let profile = if args.color_manage {
+      match &metadata.icc_profile {
+        Some(icc) => SourceProfile::new(icc),
+        None => {
+          tracing::warn!("No ICC profile found, assume the input is sRGB");
+          None
+        }
+      }
+    } else {
+      None
+    };
+
+    let (width, height) = image::image_dimensions(path).expect("Failed to read image dimensions");
+    let (width, height) = if metadata.orientation() >= 5 {
+      (height, width)
+    } else {
+      (width, height)
+    };
+
+    Ok(Self {
+      metadata,
+      profile,
+      width: width.try_into()?,
+      height: height.try_into()?,
+    })
+  }
+}
+
+/// A still image converted into the input of the network.
+pub struct Still {
+  pub source: Source,
+  pub rgb: Tensor,
+  pub alpha: Option<Vec<u8>>,
+}
+
+impl Still {
+  /// Decodes the image, returning `None` if it cannot be saved in the output format.
+  pub fn decode(
+    path: &Path,
+    mut source: Source,
+    output_format: ImageFormat,
+    device: &Device,
+  ) -> Result<Option<Self>, candle_core::Error> {
+    let img = ImageReader::open(path)
+      .expect("Failed to open image file")
+      .decode()
+      .expect("Failed to decode image file");
+    let img = source.metadata.apply_orientation(img);
+    let img = match &source.profile {
+      Some(profile) => {
+        tracing::info!("Convert the input into sRGB");
+        profile.to_srgb(img)
+      }
+      None => img,
+    };
+
+    let width: usize = img.width().try_into()?;
+    let height: usize = img.height().try_into()?;
+
+    tracing::info!(width, height, "Image file read");
+
+    let keep_alpha = match img {
+      DynamicImage::ImageRgb8(_) => {
+        tracing::info!("No alpha channel found");
+        false
+      }
+      DynamicImage::ImageRgba8(_) => {
+        if output_format == ImageFormat::Jpeg {
+          tracing::error!("Images in JPEG format cannot save transparent layers!");
+          return Ok(None);
+        }
+
+        tracing::info!("Preprocess the alpha channel...");
+        true
+      }
+      _ => {
+        if output_format == ImageFormat::Jpeg {
+          tracing::warn!("The output format is JPEG, Convert into RGB...");
+          false
+        } else {
+          tracing::warn!("Convert into RGBA...");
+          tracing::info!("Preprocess the alpha channel...");
+          true
+        }
+      }
+    };
+
+    let (rgb, alpha) = image_to_tensor(img, keep_alpha, device)?;
+
+    tracing::info!(
+      has_alpha = alpha.is_some(),
+      "Preprocess the image into tensor",
+    );
+
+    Ok(Some(Self { source, rgb, alpha }))
+  }
+
+  /// Resamples the alpha channel, restores the colour profile and saves `res`, the upscaled
+  /// image of the given target size, on a canvas of the given size.
+  pub fn save(
+    self,
+    args: &UpscaleArgs,
+    res: &Tensor,
+    (target_width, target_height): (usize, usize),
+    canvas: (usize, usize),
+    path: &Path,
+    output_format: ImageFormat,
+  ) -> Result<(), candle_core::Error> {
+    let Source {
+      mut metadata,
+      profile,
+      width,
+      height,
+    } = self.source;
+
+    metadata.scale_resolution(
+      target_width as f64 / width as f64,
+      target_height as f64 / height as f64,
+    );
+
+    let alpha = self
+      .alpha
+      .map(|alpha| {
+        let dst = resize_alpha(
+          &alpha,
+          width,
+          height,
+          target_width,
+          target_height,
+          args.filter.unwrap_or(Filter::Mitchell).into(),
+        )?;
+        tracing::info!("Alpha channel processed");
+        Ok::<_, candle_core::Error>(dst)
+      })
+      .transpose()?;
+
+    let (mut buffer, color_type) = tensor_to_buffer(res, alpha)?;
+
+    // Without the embedded profile, the output is left in sRGB
+    if let Some(profile) = profile.as_ref().filter(|_| !args.strip_metadata) {
+      profile.restore(&mut buffer, color_type);
+      tracing::info!("Convert the result back into the source profile");
+    }
+
+    let buffer = fit_canvas(
+      buffer,
+      color_type.channel_count().into(),
+      (target_width, target_height),
+      canvas,
+      args.pad_color,
+    );
+
+    save_image(
+      canvas.0,
+      canvas.1,
+      &buffer,
+      color_type,
+      path,
+      output_format,
+      args.lossless,
+    )?;
+    if !args.strip_metadata {
+      metadata.write(path, output_format);
+    }
+
+    tracing::info!(?path, "Image saved");
+
+    Ok(())
+  }
+}
@@ -0,0 +1,327 @@
+use std::{
+  panic::{self, AssertUnwindSafe},
+  path::{Path, PathBuf},
+  sync::{Arc, Mutex, MutexGuard},
+};
+
+use candle_core::Device;
+use image::ImageFormat;
+
+use crate::{
+  cli::{Filter, Fit, ModelArgs, UpscaleArgs},
+  model::{Progress, ProgressFn, RealCugan},
+  pipeline::upscale,
+  plan::{plan_passes, target_size, Pass},
+  still::{Source, Still},
+};
+
+/// The options a job of the long-running modes may set, the others keeping their defaults.
+#[derive(Clone)]
+pub struct JobOptions {
+  pub scale: u8,
+  pub denoise_level: String,
+  pub alpha: f64,
+  pub tile_size: Option<usize>,
+  pub lossless: bool,
+}
+
+impl Default for JobOptions {
+  fn default() -> Self {
+    Self {
+      scale: 2,
+      denoise_level: "0".to_owned(),
+      alpha: 1.0,
+      tile_size: None,
+      lossless: false,
+    }
+  }
+}
+
+/// Why a job failed.
+pub enum JobError {
+  /// The job cannot be done as given, e.g. a corrupted input or an output format that cannot
+  /// hold it.
+  Invalid(String),
+  Failed(candle_core::Error),
+}
+
+impl From<candle_core::Error> for JobError {
+  fn from(err: candle_core::Error) -> Self {
+    JobError::Failed(err)
+  }
+}
+
+/// Built models keyed by pass and alpha, the least recently used one being dropped once there
+/// are more than `limit`.
+struct ModelCache {
+  limit: usize,
+  /// From the least recently used to the most recently used.
+  models: Vec<(Pass, f64, Arc<RealCugan>)>,
+}
+
+impl ModelCache {
+  /// Returns the cached model of the pass and alpha, marking it as the most recently used.
+  fn get(&mut self, pass: &Pass, alpha: f64) -> Option<Arc<RealCugan>> {
+    let idx = self
+      .models
+      .iter()
+      .position(|(loaded, loaded_alpha, _)| loaded == pass && *loaded_alpha == alpha)?;
+
+    let entry = self.models.remove(idx);
+    let model = entry.2.clone();
+    self.models.push(entry);
+    Some(model)
+  }
+
+  /// Caches a loaded model, unless another job cached the same one while it was loading, and
+  /// returns the cached model.
+  fn insert(&mut self, pass: &Pass, alpha: f64, model: RealCugan) -> Arc<RealCugan> {
+    if let Some(model) = self.get(pass, alpha) {
+      return model;
+    }
+
+    if self.models.len() >= self.limit {
+      let (pass, alpha, _) = self.models.remove(0);
+      tracing::info!(
+        scale = pass.scale,
+        denoise_level = pass.denoise_level,
+        alpha,
+        tile_size = pass.tile_size,
+        "Model dropped",
+      );
+    }
+
+    let model = Arc::new(model);
+    self.models.push((pass.clone(), alpha, model.clone()));
+    model
+  }
+}
+
+/// Receives the [`Progress`] of every model of an [`Upscaler`].
+pub type SharedProgressFn = Arc<dyn Fn(Progress) + Send + Sync>;
+
+/// Upscales still images with the models kept loaded between jobs.
+pub struct Upscaler {
+  args: ModelArgs,
+  device: Device,
+  models: Mutex<ModelCache>,
+  progress: Option<SharedProgressFn>,
+}
+
+impl Upscaler {
+  pub fn new(
+    args: ModelArgs,
+    progress: Option<SharedProgressFn>,
+  ) -> Result<Self, candle_core::Error> {
+    let device = if args.use_cpu {
+      Device::Cpu
+    } else {
+      Device::new_cuda(0)?
+    };
+
+    tracing::info!(?device, "Setup device");
+
+    Ok(Self {
+      models: Mutex::new(ModelCache {
+        limit: args.max_models,
+        models: vec![],
+      }),
+      args,
+      device,
+      progress,
+    })
+  }
+
+  /// Returns the CLI arguments upscaling `input_path` into `output_path` with the given options.
+  fn upscale_args(
+    &self,
+    options: &JobOptions,
+    input_path: PathBuf,
+    output_path: PathBuf,
+  ) -> UpscaleArgs {
+    UpscaleArgs {
+      input_path,
+      output_path,
+      scale: options.scale,
+      auto_scale: false,
+      passes: vec![],
+      denoise_level: options.denoise_level.clone(),
+      denoise_strength: None,
+      model: None,
+      model_dir: self.args.model_dir.clone(),
+      lossless: options.lossless,
+      tile_size: options.tile_size,
+      batch_size: 1,
+      width: None,
+      height: None,
+      filter: None,
+      fit: Fit::Stretch,
+      pad_color: [0, 0, 0, 255],
+      no_cache: self.args.no_cache,
+      use_cpu: self.args.use_cpu,
+      timeout: None,
+      quantize: self.args.quantize,
+      quantize_report: false,
+      alpha: options.alpha,
+      share_se_stats: false,
+      color_manage: false,
+      linear_resize: false,
+      strip_metadata: false,
+    }
+  }
+
+  /// Returns the model of the pass with the alpha of `args`, loading it if it is not cached.
+  ///
+  /// The cache is not locked while loading, so that the jobs using cached models go on.
+  fn model(
+    &self,
+    pass: &Pass,
+    args: &UpscaleArgs,
+  ) -> Result<Option<Arc<RealCugan>>, candle_core::Error> {
+    if let Some(model) = self.lock_models().get(pass, args.alpha) {
+      return Ok(Some(model));
+    }
+
+    let Some(mut model) = pass.load(args, &self.device, None)? else {
+      return Ok(None);
+    };
+    // The progress bar of the CLI is replaced by the one of the upscaler, if any
+    model.set_progress(
+      self
+        .progress
+        .clone()
+        .map(|progress| Box::new(move |p| progress(p)) as ProgressFn),
+    );
+
+    tracing::info!(
+      scale = pass.scale,
+      denoise_level = pass.denoise_level,
+      alpha = args.alpha,
+      tile_size = pass.tile_size,
+      "Model loaded",
+    );
+
+    Ok(Some(self.lock_models().insert(pass, args.alpha, model)))
+  }
+
+  fn lock_models(&self) -> MutexGuard<'_, ModelCache> {
+    self.models.lock().expect("The model lock is poisoned")
+  }
+
+  /// Returns the pass `plan_passes` gives to a job with these options.
+  fn pass(&self, options: &JobOptions) -> Pass {
+    Pass {
+      scale: options.scale.into(),
+      denoise_level: options.denoise_level.clone(),
+      denoise_strength: None,
+      tile_size: options.tile_size,
+      quantize: self.args.quantize,
+    }
+  }
+
+  /// Loads the model a job with these options uses, returning `false` if it is missing.
+  pub fn preload(&self, options: &JobOptions) -> Result<bool, candle_core::Error> {
+    let args = self.upscale_args(options, PathBuf::new(), PathBuf::new());
+
+    Ok(self.model(&self.pass(options), &args)?.is_some())
+  }
+
+  /// Caches the model built from `vb` for the jobs with these options, without a model file.
+  #[cfg(test)]
+  pub fn insert_model(
+    &self,
+    options: &JobOptions,
+    vb: candle_nn::VarBuilder,
+  ) -> Result<(), candle_core::Error> {
+    let pass = self.pass(options);
+    let model = RealCugan::new(
+      pass.scale,
+      options.alpha,
+      pass.tile_size,
+      !self.args.no_cache,
+      pass.quantize,
+      vb,
+    )?;
+
+    self.lock_models().insert(&pass, options.alpha, model);
+    Ok(())
+  }
+
+  /// Upscales the image at `input_path` into `output_path`, returning the size of the result.
+  ///
+  /// Only the first frame of an animation is upscaled.
+  pub fn upscale(
+    &self,
+    options: &JobOptions,
+    input_path: &Path,
+    output_path: &Path,
+    output_format: ImageFormat,
+  ) -> Result<(usize, usize), JobError> {
+    if output_format == ImageFormat::Jpeg && options.lossless {
+      return Err(JobError::Invalid(
+        "JPEG images cannot be lossless".to_owned(),
+      ));
+    }
+
+    let args = self.upscale_args(options, input_path.to_owned(), output_path.to_owned());
+
+    // Unreadable and corrupted images panic, which must not stop the long-running modes
+    panic::catch_unwind(AssertUnwindSafe(|| {
+      self.upscale_still(&args, output_format)
+    }))
+    .unwrap_or_else(|payload| {
+      let message = match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => match payload.downcast::<&str>() {
+          Ok(message) => (*message).to_owned(),
+          Err(_) => "Failed to upscale the image".to_owned(),
+        },
+      };
+      Err(JobError::Invalid(message))
+    })
+  }
+
+  fn upscale_still(
+    &self,
+    args: &UpscaleArgs,
+    output_format: ImageFormat,
+  ) -> Result<(usize, usize), JobError> {
+    let source = Source::read(args, &args.input_path)?;
+    let plan = plan_passes(args, source.width, source.height);
+
+    let mut models = Vec::with_capacity(plan.len());
+    for pass in &plan {
+      let Some(model) = self.model(pass, args)? else {
+        return Err(JobError::Invalid("Failed to find the model".to_owned()));
+      };
+      models.push(model);
+    }
+    let passes: Vec<&RealCugan> = models.iter().map(Arc::as_ref).collect();
+
+    let Some(still) = Still::decode(&args.input_path, source, output_format, &self.device)? else {
+      return Err(JobError::Invalid(
+        "The image cannot be saved in the output format".to_owned(),
+      ));
+    };
+
+    let (target, canvas) = target_size(
+      args,
+      still.source.width,
+      still.source.height,
+      plan.iter().map(|pass| pass.scale).product(),
+    );
+
+    let res = upscale(
+      &passes,
+      &still.rgb,
+      None,
+      target.0,
+      target.1,
+      Filter::Lanczos3,
+      false,
+    )?;
+    still.save(args, &res, target, canvas, &args.output_path, output_format)?;
+
+    Ok(canvas)
+  }
+}
@@ -1,65 +1,128 @@
-use std::{fs::File, io::BufWriter, path::Path};
+use std::{
+  fs::File,
+  io::{BufWriter, Write},
+  path::Path,
+};
 
-use candle_core::{shape::Dim, DType, Tensor};
+use candle_core::{shape::Dim, DType, Module, Tensor};
+#[cfg(not(target_arch = "wasm32"))]
+use image::codecs::webp::{self, WebPEncoder};
 use image::{
   codecs::{
     bmp::BmpEncoder,
+    gif::GifEncoder,
     jpeg::JpegEncoder,
     png::{self, PngEncoder},
-    webp::{self, WebPEncoder},
   },
   ColorType, ImageEncoder, ImageFormat,
 };
+use resize::Pixel;
+use rgb::FromSlice;
+
+use crate::model::{RealCugan, SeStats};
+
+/// How [`TensorExt::pad`] fills the padding.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PadMode {
+  /// Mirrors the tensor without repeating the edge, like `torch.nn.ReflectionPad2d`. Padding
+  /// wider than the tensor keeps bouncing between both edges.
+  Reflect,
+  /// Repeats the edge, like `torch.nn.ReplicationPad2d`.
+  Replicate,
+}
+
+impl PadMode {
+  /// Returns the index in a dimension of length `len` that fills the position `i`, which is
+  /// relative to the start of the unpadded dimension.
+  fn source_index(self, i: isize, len: usize) -> usize {
+    let last = len as isize - 1;
+
+    match self {
+      PadMode::Replicate => i.clamp(0, last) as usize,
+      PadMode::Reflect if last == 0 => 0,
+      PadMode::Reflect => {
+        let period = last * 2;
+        let i = i.rem_euclid(period);
+        (if i > last { period - i } else { i }) as usize
+      }
+    }
+  }
+}
 
 pub trait TensorExt {
-  fn reflection_pad<D: Dim>(
+  /// Pads the consecutive dimensions starting at `dim`, `pads` holding the `(left, right)`
+  /// padding of each of them.
+  ///
+  /// Each padded dimension is filled with its own `index_select`.
+  fn pad<D: Dim>(
     &self,
     dim: D,
-    left: usize,
-    right: usize,
+    pads: &[(usize, usize)],
+    mode: PadMode,
   ) -> Result<Self, candle_core::Error>
   where
     Self: Sized;
 }
 
 impl TensorExt for Tensor {
-  fn reflection_pad<D: Dim>(
+  fn pad<D: Dim>(
     &self,
     dim: D,
-    left: usize,
-    right: usize,
+    pads: &[(usize, usize)],
+    mode: PadMode,
   ) -> Result<Self, candle_core::Error> {
-    if left == 0 && right == 0 {
-      Ok(self.clone())
-    } else if self.elem_count() == 0 {
-      Err(candle_core::Error::Msg("cannot use reflection_pad on an empty tensor".to_owned()).bt())
-    } else if left == 0 {
-      let dim = dim.to_index(self.shape(), "reflection_pad")?;
-      let mut v = vec![self.clone()];
-      for i in 0..right {
-        v.push(self.narrow(dim, self.dim(dim)? - i - 2, 1)?)
-      }
-      Tensor::cat(&v, dim)
-    } else if right == 0 {
-      let dim = dim.to_index(self.shape(), "reflection_pad")?;
-      let mut v = vec![];
-      for i in 0..left {
-        v.push(self.narrow(dim, left - i, 1)?)
-      }
-      v.push(self.clone());
-      Tensor::cat(&v, dim)
-    } else {
-      let dim = dim.to_index(self.shape(), "reflection_pad")?;
-      let mut v = vec![];
-      for i in 0..left {
-        v.push(self.narrow(dim, left - i, 1)?)
-      }
-      v.push(self.clone());
-      for i in 0..right {
-        v.push(self.narrow(dim, self.dim(dim)? - i - 2, 1)?)
+    let dim = dim.to_index(self.shape(), "pad")?;
+    let end = dim + pads.len();
+
+    if end > self.rank() {
+      return Err(
+        candle_core::Error::Msg(format!(
+          "cannot pad {} dimensions from dimension {dim} of a tensor of rank {}",
+          pads.len(),
+          self.rank()
+        ))
+        .bt(),
+      );
+    }
+
+    if pads.iter().all(|&(left, right)| left == 0 && right == 0) {
+      return Ok(self.clone());
+    }
+
+    if self.elem_count() == 0 {
+      return Err(candle_core::Error::Msg("cannot pad an empty tensor".to_owned()).bt());
+    }
+
+    let mut res = self.clone();
+
+    for (d, &(left, right)) in (dim..end).zip(pads) {
+      if left == 0 && right == 0 {
+        continue;
       }
-      Tensor::cat(&v, dim)
+
+      let len = res.dim(d)?;
+      let Some(out_len) = left
+        .checked_add(len)
+        .and_then(|n| n.checked_add(right))
+        .filter(|&n| u32::try_from(n).is_ok())
+      else {
+        return Err(
+          candle_core::Error::Msg(format!(
+            "cannot pad dimension {d} of length {len} by ({left}, {right}), beyond u32::MAX"
+          ))
+          .bt(),
+        );
+      };
+
+      let sources: Vec<u32> = (0..out_len)
+        .map(|i| mode.source_index(i as isize - left as isize, len) as u32)
+        .collect();
+      let index = Tensor::from_vec(sources, out_len, self.device())?;
+
+      res = res.index_select(&index, d)?;
     }
+
+    Ok(res)
   }
 }
 
@@ -73,62 +136,205 @@ pub fn preprocess_alpha_channel(data: &Tensor) -> Result<(Tensor, Vec<u8>), cand
   return Ok(((rgb * alpha_mask)?, raw_alpha));
 }
 
-pub fn save_image(
-  width: usize,
-  height: usize,
+/// Converts the `(height, width, 3)` result back into raw 8-bit pixels, restoring the
+/// premultiplied colour channels if an alpha channel is given.
+pub fn tensor_to_buffer(
   rgb: &Tensor,
   alpha: Option<Vec<u8>>,
-  path: impl AsRef<Path>,
-  format: ImageFormat,
-  lossless: bool,
-) -> Result<(), candle_core::Error> {
-  let (buffer, color_type) = if let Some(alpha) = alpha {
+) -> Result<(Vec<u8>, ColorType), candle_core::Error> {
+  if let Some(alpha) = alpha {
+    let (height, width, _) = rgb.shape().dims3()?;
+
     let alpha = Tensor::from_vec(alpha, (height, width, 1), rgb.device())?;
     let alpha_mask = (255. / Tensor::cat(&[&alpha, &alpha, &alpha], 2)?.to_dtype(DType::F32)?)?;
 
     let rgb = (rgb * alpha_mask)?.clamp(0., 255.)?.to_dtype(DType::U8)?;
 
-    (
+    Ok((
       Tensor::cat(&[rgb, alpha], 2)?.flatten_all()?.to_vec1()?,
       ColorType::Rgba8,
-    )
+    ))
   } else {
-    (
+    Ok((
       rgb
         .clamp(0., 255.)?
         .to_dtype(DType::U8)?
         .flatten_all()?
         .to_vec1()?,
       ColorType::Rgb8,
-    )
+    ))
+  }
+}
+
+/// Converts a `(n, height, width, 3)` tensor into the normalized `(n, 3, height, width)` input of
+/// the network.
+pub fn to_network_input(rgb: &Tensor) -> Result<Tensor, candle_core::Error> {
+  let data = rgb.permute((0, 3, 1, 2))?;
+  (data / (255. / 0.7))? + 0.15 // for pro model
+}
+
+/// Runs one pass of Real-CUGAN on a `(n, height, width, 3)` tensor, without rounding the result
+/// so that chained passes keep full precision.
+///
+/// Each image of the batch uses its own SE statistics, unless `stats` is given.
+pub fn infer(
+  model: &RealCugan,
+  rgb: &Tensor,
+  stats: Option<&SeStats>,
+) -> Result<Tensor, candle_core::Error> {
+  let data = to_network_input(rgb)?;
+
+  let res = match stats {
+    Some(stats) => model.forward_with_stats(&data, stats)?,
+    None => model.forward(&data)?,
   };
+  drop(data);
+
+  let res = ((res - 0.15)? * (255. / 0.7))?; // for pro model
+  res.permute((0, 2, 3, 1))
+}
+
+/// Resamples the alpha channel to the target size.
+pub fn resize_alpha(
+  alpha: &[u8],
+  width: usize,
+  height: usize,
+  target_width: usize,
+  target_height: usize,
+  filter: resize::Type,
+) -> Result<Vec<u8>, candle_core::Error> {
+  let mut resizer = resize::new(
+    width,
+    height,
+    target_width,
+    target_height,
+    Pixel::Gray8,
+    filter,
+  )
+  .map_err(|err| {
+    candle_core::Error::Msg(format!(
+      "Failed to initialize the alpha channel resizer: {err}"
+    ))
+    .bt()
+  })?;
+
+  let mut dst = vec![0; target_width * target_height];
+
+  resizer
+    .resize(alpha.as_gray(), dst.as_gray_mut())
+    .map_err(|err| {
+      candle_core::Error::Msg(format!("Failed to upscale the alpha channel: {err}")).bt()
+    })?;
+
+  Ok(dst)
+}
+
+/// Upscales a `(height, width, 3 | 4)` tensor of 8-bit pixels with one model like the CLI does,
+/// returning the raw pixels of the result and its size.
+pub fn upscale_pixels(
+  model: &RealCugan,
+  data: &Tensor,
+) -> Result<(Vec<u8>, usize, usize), candle_core::Error> {
+  let (height, width, channels) = data.dims3()?;
+
+  let (rgb, alpha) = if channels == 4 {
+    let (rgb, alpha) = preprocess_alpha_channel(data)?;
+    (rgb, Some(alpha))
+  } else {
+    (data.to_dtype(DType::F32)?, None)
+  };
+
+  let res = infer(model, &rgb.unsqueeze(0)?, None)?
+    .round()?
+    .squeeze(0)?;
+  let (target_height, target_width, _) = res.dims3()?;
+
+  let alpha = alpha
+    .map(|alpha| {
+      resize_alpha(
+        &alpha,
+        width,
+        height,
+        target_width,
+        target_height,
+        resize::Type::Mitchell,
+      )
+    })
+    .transpose()?;
 
+  let (buffer, _) = tensor_to_buffer(&res, alpha)?;
+  Ok((buffer, target_width, target_height))
+}
+
+pub fn save_image(
+  width: usize,
+  height: usize,
+  buffer: &[u8],
+  color_type: ColorType,
+  path: impl AsRef<Path>,
+  format: ImageFormat,
+  lossless: bool,
+) -> Result<(), candle_core::Error> {
+  let buffered_file_write = BufWriter::new(File::create(path)?);
+
+  encode_image(
+    width,
+    height,
+    buffer,
+    color_type,
+    buffered_file_write,
+    format,
+    lossless,
+  )
+}
+
+/// Same as [`save_image`], but writes the encoded image into `writer`.
+///
+/// WebP images cannot be encoded on wasm32, where libwebp is not built.
+pub fn encode_image(
+  width: usize,
+  height: usize,
+  buffer: &[u8],
+  color_type: ColorType,
+  mut writer: impl Write,
+  format: ImageFormat,
+  lossless: bool,
+) -> Result<(), candle_core::Error> {
   let width = width.try_into()?;
   let height = height.try_into()?;
 
-  let mut buffered_file_write =
-    BufWriter::new(File::create(path).expect("Failed to create output image file"));
-
   match format {
     ImageFormat::Bmp => {
       if !lossless {
         tracing::warn!("BMP images cannot be lossy, output lossless result...");
       }
 
-      BmpEncoder::new(&mut buffered_file_write).write_image(&buffer, width, height, color_type)
+      BmpEncoder::new(&mut writer).write_image(buffer, width, height, color_type)
+    }
+
+    ImageFormat::Gif => {
+      if lossless {
+        tracing::warn!("GIF images are limited to 256 colors, output lossy result...");
+      }
+
+      GifEncoder::new(writer).encode(buffer, width, height, color_type)
     }
 
     ImageFormat::Jpeg => {
       if lossless {
-        panic!("JPEG images cannot be lossless");
+        return Err(candle_core::Error::Msg("JPEG images cannot be lossless".to_owned()).bt());
       }
 
       if color_type == ColorType::Rgba8 {
-        panic!("Images in JPEG format cannot save transparent layers!");
+        return Err(
+          candle_core::Error::Msg(
+            "Images in JPEG format cannot save transparent layers".to_owned(),
+          )
+          .bt(),
+        );
       }
 
-      JpegEncoder::new_with_quality(buffered_file_write, 100)
-        .write_image(&buffer, width, height, color_type)
+      JpegEncoder::new_with_quality(writer, 100).write_image(buffer, width, height, color_type)
     }
 
     ImageFormat::Png => {
@@ -137,28 +343,160 @@ pub fn save_image(
       }
 
       PngEncoder::new_with_quality(
-        buffered_file_write,
+        writer,
         png::CompressionType::Fast,
         png::FilterType::Adaptive,
       )
-      .write_image(&buffer, width, height, color_type)
+      .write_image(buffer, width, height, color_type)
     }
 
+    #[cfg(not(target_arch = "wasm32"))]
     ImageFormat::WebP => WebPEncoder::new_with_quality(
-      buffered_file_write,
+      writer,
       if lossless {
         webp::WebPQuality::lossless()
       } else {
         webp::WebPQuality::lossy(100)
       },
     )
-    .write_image(&buffer, width, height, color_type),
+    .write_image(buffer, width, height, color_type),
 
     _ => {
-      panic!("Unsupported output image format");
+      return Err(
+        candle_core::Error::Msg(format!("Unsupported output image format {format:?}")).bt(),
+      );
     }
   }
-  .expect("Failed to encode image & write to file");
+  .map_err(|err| candle_core::Error::Msg(format!("Failed to encode the image: {err}")).bt())
+}
+
+#[cfg(test)]
+mod tests {
+  use candle_core::Device;
+  use proptest::prelude::*;
+
+  use super::*;
 
-  Ok(())
+  /// Index of the naive reference, which bounces off the edges one step at a time.
+  fn naive_source_index(mut i: isize, len: usize, mode: PadMode) -> usize {
+    let last = len as isize - 1;
+
+    match mode {
+      PadMode::Replicate => i.max(0).min(last) as usize,
+      PadMode::Reflect => {
+        while last > 0 && !(0..=last).contains(&i) {
+          i = if i < 0 { -i } else { last * 2 - i };
+        }
+        i.clamp(0, last) as usize
+      }
+    }
+  }
+
+  /// Pads element by element.
+  fn naive_pad(
+    data: &[f32],
+    dims: &[usize],
+    dim: usize,
+    pads: &[(usize, usize)],
+    mode: PadMode,
+  ) -> Vec<f32> {
+    let mut out_dims = dims.to_vec();
+    for (d, &(left, right)) in pads.iter().enumerate() {
+      out_dims[dim + d] += left + right;
+    }
+
+    let mut res = vec![];
+    let mut pos = vec![0; dims.len()];
+
+    for _ in 0..out_dims.iter().product() {
+      let mut offset = 0;
+      for (d, &p) in pos.iter().enumerate() {
+        let i = match d.checked_sub(dim).and_then(|d| pads.get(d)) {
+          Some(&(left, _)) => naive_source_index(p as isize - left as isize, dims[d], mode),
+          None => p,
+        };
+        offset = offset * dims[d] + i;
+      }
+      res.push(data[offset]);
+
+      for d in (0..pos.len()).rev() {
+        pos[d] += 1;
+        if pos[d] < out_dims[d] {
+          break;
+        }
+        pos[d] = 0;
+      }
+    }
+
+    res
+  }
+
+  fn case() -> impl Strategy<Value = (Vec<usize>, usize, Vec<(usize, usize)>, PadMode)> {
+    (prop::collection::vec(1..6usize, 1..=4), any::<bool>()).prop_flat_map(|(dims, reflect)| {
+      let rank = dims.len();
+      (Just(dims), 0..rank, Just(reflect)).prop_flat_map(move |(dims, dim, reflect)| {
+        (
+          Just(dims),
+          Just(dim),
+          prop::collection::vec((0..12usize, 0..12usize), 0..=rank - dim),
+          Just(if reflect {
+            PadMode::Reflect
+          } else {
+            PadMode::Replicate
+          }),
+        )
+      })
+    })
+  }
+
+  proptest! {
+    #[test]
+    fn pad_matches_naive((dims, dim, pads, mode) in case()) {
+      let len = dims.iter().product::<usize>();
+      let data: Vec<f32> = (0..len).map(|v| v as f32).collect();
+
+      let x = Tensor::from_vec(data.clone(), dims.as_slice(), &Device::Cpu).unwrap();
+      let padded = x.pad(dim, &pads, mode).unwrap();
+
+      let mut out_dims = dims.clone();
+      for (d, &(left, right)) in pads.iter().enumerate() {
+        out_dims[dim + d] += left + right;
+      }
+
+      prop_assert_eq!(padded.dims(), out_dims.as_slice());
+      prop_assert_eq!(
+        padded.flatten_all().unwrap().to_vec1::<f32>().unwrap(),
+        naive_pad(&data, &dims, dim, &pads, mode)
+      );
+    }
+  }
+
+  #[test]
+  fn reflect_matches_pytorch() {
+    let x = Tensor::new(&[1f32, 2., 3., 4.], &Device::Cpu).unwrap();
+    let padded = x.pad(0, &[(3, 2)], PadMode::Reflect).unwrap();
+
+    // torch.nn.functional.pad(x, (3, 2), mode="reflect")
+    assert_eq!(
+      padded.to_vec1::<f32>().unwrap(),
+      [4., 3., 2., 1., 2., 3., 4., 3., 2.]
+    );
+  }
+
+  #[test]
+  fn pad_past_last_dim_fails() {
+    let x = Tensor::zeros((2, 2), DType::F32, &Device::Cpu).unwrap();
+    assert!(x.pad(1, &[(1, 1), (1, 1)], PadMode::Reflect).is_err());
+  }
+
+  #[test]
+  fn pad_beyond_u32_fails() {
+    let x = Tensor::zeros((2, 2), DType::F32, &Device::Cpu).unwrap();
+    let pad = u32::MAX as usize - 1;
+
+    assert!(x.pad(1, &[(pad, 0)], PadMode::Replicate).is_err());
+    assert!(x
+      .pad(0, &[(0, 0), (1, usize::MAX)], PadMode::Replicate)
+      .is_err());
+  }
 }
@@ -0,0 +1,217 @@
+use std::path::{Path, PathBuf};
+
+use candle_core::{DType, Device, Tensor};
+use image::{io::Reader as ImageReader, ColorType, ImageFormat};
+
+use crate::{
+  cli::{Fit, UpscaleArgs, VerifyArgs},
+  metrics::psnr,
+  pipeline::image_to_tensor,
+  plan::Pass,
+  setup::output_format,
+  utils::{infer, save_image},
+};
+
+/// Returns the CLI arguments loading the model described by `args`.
+fn upscale_args(args: &VerifyArgs) -> UpscaleArgs {
+  UpscaleArgs {
+    input_path: args.input_path.clone(),
+    output_path: PathBuf::new(),
+    scale: args.scale,
+    auto_scale: false,
+    passes: vec![],
+    denoise_level: args.denoise_level.clone(),
+    denoise_strength: None,
+    model: args.model.clone(),
+    model_dir: args.model_dir.clone(),
+    lossless: false,
+    tile_size: args.tile_size,
+    batch_size: 1,
+    width: None,
+    height: None,
+    filter: None,
+    fit: Fit::Stretch,
+    pad_color: [0, 0, 0, 255],
+    no_cache: args.no_cache,
+    use_cpu: args.use_cpu,
+    timeout: None,
+    quantize: false,
+    quantize_report: false,
+    alpha: args.alpha,
+    share_se_stats: false,
+    color_manage: false,
+    linear_resize: false,
+    strip_metadata: false,
+  }
+}
+
+/// Reads the reference output as a `(height, width, 3)` tensor of `[0, 255]` values, returning
+/// `None` if it cannot be used.
+///
+/// `.npy` arrays may be laid out as `(height, width, 3)` like NumPy images or as
+/// `([1,] 3, height, width)` like PyTorch tensors.
+fn read_reference(
+  path: &Path,
+  npy_max: Option<f64>,
+  device: &Device,
+) -> Result<Option<Tensor>, candle_core::Error> {
+  if path.extension().is_some_and(|ext| ext == "npy") {
+    let array = match Tensor::read_npy(path) {
+      Ok(array) => array,
+      Err(err) => {
+        tracing::error!(?path, "Failed to read the reference: {err}");
+        return Ok(None);
+      }
+    };
+
+    let max = npy_max.unwrap_or(if array.dtype() == DType::U8 { 255. } else { 1. });
+    let array = (array.to_dtype(DType::F32)? * (255. / max))?;
+
+    let array = match *array.dims() {
+      [_, _, 3] => array,
+      [1, _, _, 3] => array.squeeze(0)?,
+      [3, _, _] => array.permute((1, 2, 0))?,
+      [1, 3, _, _] => array.squeeze(0)?.permute((1, 2, 0))?,
+      ref dims => {
+        tracing::error!(?dims, "The reference must be an RGB image");
+        return Ok(None);
+      }
+    };
+
+    return Ok(Some(array.contiguous()?.to_device(device)?));
+  }
+
+  if npy_max.is_some() {
+    tracing::warn!("The reference is not a `.npy` array, ignoring `--npy-max`...");
+  }
+
+  let img = match ImageReader::open(path).map(|reader| reader.decode()) {
+    Ok(Ok(img)) => img,
+    Ok(Err(err)) => {
+      tracing::error!(?path, "Failed to decode the reference: {err}");
+      return Ok(None);
+    }
+    Err(err) => {
+      tracing::error!(?path, "Failed to read the reference: {err}");
+      return Ok(None);
+    }
+  };
+  let (height, width) = (img.height() as usize, img.width() as usize);
+
+  // Keeps the 16 bits of the PNG output of PyTorch
+  let data = img.into_rgb32f().into_raw();
+  Ok(Some(
+    (Tensor::from_vec(data, (height, width, 3), device)? * 255.)?,
+  ))
+}
+
+/// Colours `[0, 1]` values from black through red and yellow to white.
+fn heatmap(t: &Tensor) -> Result<Tensor, candle_core::Error> {
+  let channels = (0..3)
+    .map(|c| ((t * 3.)? - c as f64)?.clamp(0f32, 1f32))
+    .collect::<Result<Vec<_>, _>>()?;
+
+  (Tensor::stack(&channels, 2)? * 255.)?
+    .round()?
+    .to_dtype(DType::U8)
+}
+
+/// Upscales the input with the given model and compares the unrounded result with the reference.
+pub fn verify(args: VerifyArgs) -> Result<(), candle_core::Error> {
+  let heatmap_format = match &args.heatmap {
+    Some(path) => match output_format(path, false) {
+      Ok(format) => Some(format),
+      Err(err) => {
+        tracing::error!("{err}");
+        return Ok(());
+      }
+    },
+    None => None,
+  };
+
+  let device = if args.use_cpu {
+    Device::Cpu
+  } else {
+    Device::new_cuda(0)?
+  };
+
+  tracing::info!(?device, "Setup device");
+
+  let Some(reference) = read_reference(&args.reference, args.npy_max, &device)? else {
+    return Ok(());
+  };
+
+  let pass = Pass {
+    scale: args.scale.into(),
+    denoise_level: args.denoise_level.clone(),
+    denoise_strength: None,
+    tile_size: args.tile_size,
+    quantize: false,
+  };
+  let Some(model) = pass.load(&upscale_args(&args), &device, None)? else {
+    return Ok(());
+  };
+
+  // The PyTorch implementation only upscales the colour channels
+  let img = ImageReader::open(&args.input_path)
+    .expect("Failed to open image file")
+    .decode()
+    .expect("Failed to decode image file");
+  let (rgb, _) = image_to_tensor(img, false, &device)?;
+
+  let res = infer(&model, &rgb.unsqueeze(0)?, None)?
+    .squeeze(0)?
+    .clamp(0f32, 255f32)?;
+
+  if res.dims() != reference.dims() {
+    tracing::error!(
+      output = ?res.dims(),
+      reference = ?reference.dims(),
+      "The reference does not have the size of the output"
+    );
+    return Ok(());
+  }
+
+  // The largest difference of the channels of each pixel
+  let diff = (&res - &reference)?.abs()?.max(2)?;
+  let width = diff.dim(1)?;
+
+  let max_diff: f32 = diff.max_all()?.to_scalar()?;
+  let mean_diff: f32 = (&res - &reference)?.abs()?.mean_all()?.to_scalar()?;
+  let at = diff.flatten_all()?.argmax(0)?.to_scalar::<u32>()? as usize;
+
+  tracing::info!(
+    max_diff = format!("{max_diff:.4}"),
+    at = ?(at % width, at / width),
+    mean_diff = format!("{mean_diff:.4}"),
+    psnr = format!("{:.2} dB", psnr(&res, &reference)?),
+    "Parity report",
+  );
+
+  if let (Some(path), Some(format)) = (&args.heatmap, heatmap_format) {
+    let map = heatmap(&(diff / f64::from(max_diff.max(1.)))?)?;
+    save_image(
+      width,
+      map.dim(0)?,
+      &map.flatten_all()?.to_vec1()?,
+      ColorType::Rgb8,
+      path,
+      format,
+      !matches!(format, ImageFormat::Gif | ImageFormat::Jpeg),
+    )?;
+
+    tracing::info!(?path, "Heatmap saved");
+  }
+
+  // The error makes the process exit with a failure, which lets scripts and CI catch the regression
+  if let Some(limit) = args.max_diff.filter(|&limit| f64::from(max_diff) > limit) {
+    return Err(
+      candle_core::Error::Msg(format!(
+        "The output differs from the reference by {max_diff:.4}, more than the limit of {limit}"
+      ))
+      .bt(),
+    );
+  }
+
+  Ok(())
+}
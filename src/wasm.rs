@@ -0,0 +1,146 @@
+//! The WebAssembly entry point, built with
+//! `wasm-pack build --target web -- --no-default-features --features wasm`.
+
+use candle_core::{DType, Device, Tensor};
+use candle_nn::VarBuilder;
+use image::{ColorType, ImageFormat};
+use js_sys::{Function, Object, Reflect};
+use wasm_bindgen::prelude::*;
+
+use crate::{
+  model::{Progress, ProgressFn, RealCugan},
+  utils::{encode_image, upscale_pixels},
+};
+
+/// The formats `encode_image` can write on wasm32.
+const OUTPUT_FORMATS: [ImageFormat; 4] = [
+  ImageFormat::Bmp,
+  ImageFormat::Gif,
+  ImageFormat::Jpeg,
+  ImageFormat::Png,
+];
+
+/// A JavaScript progress callback.
+struct Callback(Function);
+
+// SAFETY: without the `atomics` target feature, wasm32 has no shared memory and runs the module
+// on the single thread of its page or worker, so the callback is never sent to or shared with
+// another thread. With threads, the impls are left out and setting the callback does not compile.
+#[cfg(all(target_arch = "wasm32", not(target_feature = "atomics")))]
+unsafe impl Send for Callback {}
+// SAFETY: see `Send` above
+#[cfg(all(target_arch = "wasm32", not(target_feature = "atomics")))]
+unsafe impl Sync for Callback {}
+
+impl Callback {
+  fn call(&self, progress: Progress) {
+    let arg = Object::new();
+    for (key, value) in [
+      ("stage", progress.stage),
+      ("stageNum", Progress::STAGE_NUM),
+      ("tile", progress.tile),
+      ("tileNum", progress.tile_num),
+    ] {
+      Reflect::set(&arg, &key.into(), &value.into()).expect("Setting a field of an object");
+    }
+
+    // The upscaling goes on whatever the callback does
+    let _ = self.0.call1(&JsValue::NULL, &arg);
+  }
+}
+
+/// Upscales encoded images on the CPU with a Real-CUGAN model.
+#[wasm_bindgen]
+pub struct Upscaler {
+  model: RealCugan,
+}
+
+#[wasm_bindgen]
+impl Upscaler {
+  /// Loads a model from the bytes of its `.safetensors` file, whose scale must be `scale`.
+  ///
+  /// A `tileSize` of 0 runs on the whole image at once, which needs far more memory.
+  #[wasm_bindgen(constructor)]
+  pub fn new(
+    model: &[u8],
+    scale: usize,
+    alpha: f64,
+    tile_size: usize,
+    no_cache: bool,
+  ) -> Result<Upscaler, JsError> {
+    let tensors = candle_core::safetensors::load_buffer(model, &Device::Cpu)?;
+    let vb = VarBuilder::from_tensors(tensors, DType::F32, &Device::Cpu);
+
+    let tile_size = (tile_size > 0).then_some(tile_size);
+    let model = RealCugan::new(scale, alpha, tile_size, !no_cache, false, vb)?;
+
+    Ok(Self { model })
+  }
+
+  /// Upscales an encoded image into `format` (`png`, `jpeg`, `bmp` or `gif`), the format of the
+  /// input if not given, and returns the encoded result.
+  ///
+  /// When tiled, `progress` is called after each tile with an object holding `stage`,
+  /// `stageNum`, `tile` and `tileNum`. It runs synchronously, so run the upscaler in a worker and
+  /// post the progress to the page from there.
+  pub fn upscale(
+    &mut self,
+    image: &[u8],
+    format: Option<String>,
+    progress: Option<Function>,
+  ) -> Result<Vec<u8>, JsError> {
+    let format = match format {
+      Some(format) => ImageFormat::from_extension(&format)
+        .filter(|format| OUTPUT_FORMATS.contains(format))
+        .ok_or_else(|| JsError::new(&format!("unsupported output format {format:?}")))?,
+      None => image::guess_format(image)?,
+    };
+    if !OUTPUT_FORMATS.contains(&format) {
+      return Err(JsError::new(&format!(
+        "cannot encode {format:?} images, choose another output format"
+      )));
+    }
+
+    let img = image::load_from_memory(image)?;
+    let (width, height) = (img.width() as usize, img.height() as usize);
+
+    // JPEG images cannot keep the alpha channel
+    let keep_alpha = img.color().has_alpha() && format != ImageFormat::Jpeg;
+    let data = if keep_alpha {
+      Tensor::from_vec(
+        img.into_rgba8().into_raw(),
+        (height, width, 4),
+        &Device::Cpu,
+      )?
+    } else {
+      Tensor::from_vec(img.into_rgb8().into_raw(), (height, width, 3), &Device::Cpu)?
+    };
+
+    self.model.set_progress(progress.map(|progress| {
+      let progress = Callback(progress);
+      Box::new(move |p| progress.call(p)) as ProgressFn
+    }));
+
+    let (buffer, width, height) = upscale_pixels(&self.model, &data)?;
+    drop(data);
+
+    let color_type = if keep_alpha {
+      ColorType::Rgba8
+    } else {
+      ColorType::Rgb8
+    };
+
+    let mut res = vec![];
+    encode_image(
+      width,
+      height,
+      &buffer,
+      color_type,
+      &mut res,
+      format,
+      matches!(format, ImageFormat::Bmp | ImageFormat::Png),
+    )?;
+
+    Ok(res)
+  }
+}
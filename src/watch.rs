@@ -0,0 +1,214 @@
+use std::{
+  collections::HashMap,
+  fs::{self, File, OpenOptions},
+  io::{BufRead, BufReader, Write},
+  panic::{self, AssertUnwindSafe},
+  path::{Path, PathBuf},
+  thread,
+  time::{Duration, UNIX_EPOCH},
+};
+
+use candle_core::Device;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  batch::{is_image, list_files, same_dirs, upscale_group, LoadedModels},
+  cli::{UpscaleArgs, WatchArgs},
+  setup::setup_args,
+  still::Source,
+};
+
+const RECORD_FILE: &str = ".real-cugan-rs-watch.jsonl";
+
+/// The size and modification time of a file, which change while it is written.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct Stamp {
+  size: u64,
+  /// Since the Unix epoch
+  modified: Duration,
+}
+
+impl Stamp {
+  fn read(path: &Path) -> Option<Self> {
+    let metadata = fs::metadata(path).ok()?;
+
+    Some(Self {
+      size: metadata.len(),
+      modified: metadata
+        .modified()
+        .ok()?
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default(),
+    })
+  }
+}
+
+/// A line of the record file.
+#[derive(Serialize, Deserialize)]
+struct Entry {
+  name: String,
+  #[serde(flatten)]
+  stamp: Stamp,
+}
+
+/// The input files already handled, stored as one JSON line per file so that a restart skips
+/// them. The last line of a file name wins.
+struct Record {
+  file: File,
+  done: HashMap<String, Stamp>,
+}
+
+impl Record {
+  fn open(path: &Path) -> Self {
+    let mut done = HashMap::new();
+
+    if let Ok(file) = File::open(path) {
+      for line in BufReader::new(file).lines() {
+        let line = line.expect("Failed to read the record file");
+
+        // A line cut short by a crash only makes its file upscaled again
+        match serde_json::from_str::<Entry>(&line) {
+          Ok(entry) => {
+            done.insert(entry.name, entry.stamp);
+          }
+          Err(err) => tracing::warn!(?path, "Invalid line in the record file: {err}"),
+        }
+      }
+    }
+
+    let file = OpenOptions::new()
+      .create(true)
+      .append(true)
+      .open(path)
+      .expect("Failed to open the record file");
+
+    Self { file, done }
+  }
+
+  fn is_done(&self, name: &str, stamp: Stamp) -> bool {
+    self.done.get(name) == Some(&stamp)
+  }
+
+  fn add(&mut self, name: String, stamp: Stamp) {
+    let entry = Entry { name, stamp };
+    let line = serde_json::to_string(&entry).expect("Record entries are valid JSON");
+    writeln!(self.file, "{line}").expect("Failed to write the record file");
+
+    self.done.insert(entry.name, entry.stamp);
+  }
+}
+
+/// Scans the input directory every `--interval` and upscales the new and modified images into
+/// the output directory, keeping the file names and formats like a directory input does.
+///
+/// A file is only upscaled once its size and modification time stay the same between two scans,
+/// so that files still being written are skipped until they are complete.
+pub fn watch(args: WatchArgs) -> Result<(), candle_core::Error> {
+  let upscale = &args.upscale;
+
+  if !upscale.input_path.is_dir() {
+    tracing::error!("The input path must be a directory to watch");
+    return Ok(());
+  }
+
+  if let Err(err) = setup_args(upscale) {
+    tracing::error!("{err}");
+    return Ok(());
+  }
+
+  if upscale.timeout.is_some() {
+    tracing::warn!("`--timeout` does not apply to watching, ignoring it...");
+  }
+
+  fs::create_dir_all(&upscale.output_path).expect("Failed to create the output directory");
+
+  if same_dirs(upscale) {
+    return Ok(());
+  }
+
+  let device = if upscale.use_cpu {
+    Device::Cpu
+  } else {
+    Device::new_cuda(0)?
+  };
+
+  tracing::info!(?device, "Setup device");
+
+  let record_path = args
+    .record
+    .clone()
+    .unwrap_or_else(|| upscale.output_path.join(RECORD_FILE));
+  let mut record = Record::open(&record_path);
+
+  let mut models = LoadedModels::default();
+  // Files that changed since the scan before the last one
+  let mut changing: HashMap<PathBuf, Stamp> = HashMap::new();
+
+  tracing::info!(path = ?upscale.input_path, "Watching the input directory");
+
+  loop {
+    let mut still_changing = HashMap::new();
+
+    for path in list_files(&upscale.input_path) {
+      if path == record_path {
+        continue;
+      }
+
+      let Some(stamp) = Stamp::read(&path) else {
+        continue;
+      };
+      let name = path
+        .file_name()
+        .expect("Listed files have a name")
+        .to_string_lossy()
+        .into_owned();
+
+      if record.is_done(&name, stamp) {
+        continue;
+      }
+
+      if changing.get(&path) != Some(&stamp) {
+        still_changing.insert(path, stamp);
+        continue;
+      }
+
+      if is_image(&path) && !upscale_image(upscale, &device, &mut models, &path)? {
+        return Ok(());
+      }
+
+      record.add(name, stamp);
+    }
+
+    changing = still_changing;
+    thread::sleep(args.interval);
+  }
+}
+
+/// Upscales an image of the input directory, returning `false` if a model is missing.
+///
+/// Failures are logged rather than returned to keep watching, and the image is only tried again
+/// once modified.
+fn upscale_image(
+  args: &UpscaleArgs,
+  device: &Device,
+  models: &mut LoadedModels,
+  path: &Path,
+) -> Result<bool, candle_core::Error> {
+  // Corrupted images panic while decoding
+  let res = panic::catch_unwind(AssertUnwindSafe(|| {
+    let source = Source::read(args, path)?;
+    upscale_group(args, device, None, models, vec![(path.to_owned(), source)])
+  }));
+
+  match res {
+    Ok(Ok(found)) => Ok(found),
+    Ok(Err(err)) => {
+      tracing::error!(?path, "Failed to upscale the image: {err}");
+      Ok(true)
+    }
+    Err(_) => {
+      tracing::error!(?path, "Failed to upscale the image");
+      Ok(true)
+    }
+  }
+}
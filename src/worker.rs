@@ -0,0 +1,157 @@
+use std::{
+  io::{self, BufRead, Write},
+  path::PathBuf,
+  sync::{Arc, Mutex},
+  time::Instant,
+};
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{
+  cli::WorkerArgs,
+  model::Progress,
+  setup::output_format,
+  upscaler::{JobError, JobOptions, Upscaler},
+};
+
+/// A job read from stdin, e.g. `{"input": "a.png", "output": "b.png", "scale": 2, "denoise": 0}`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Job {
+  /// Echoed in the lines about the job, the line number of the job if not given.
+  id: Option<Value>,
+  input: PathBuf,
+  output: PathBuf,
+  scale: Option<u8>,
+  denoise: Option<i8>,
+  alpha: Option<f64>,
+  tile: Option<usize>,
+  #[serde(default)]
+  lossless: bool,
+}
+
+impl Job {
+  fn options(&self) -> Result<JobOptions, String> {
+    let defaults = JobOptions::default();
+
+    let scale = match self.scale {
+      Some(scale @ (2 | 3)) => scale,
+      Some(scale) => return Err(format!("unsupported scale {scale}")),
+      None => defaults.scale,
+    };
+    let denoise_level = match self.denoise {
+      Some(denoise @ (-1 | 0 | 3)) => denoise.to_string(),
+      Some(denoise) => return Err(format!("unsupported denoise level {denoise}")),
+      None => defaults.denoise_level,
+    };
+    let tile_size = match self.tile {
+      Some(0) => return Err("the tile size must be positive".to_owned()),
+      Some(tile_size) => Some(tile_size),
+      None => defaults.tile_size,
+    };
+
+    Ok(JobOptions {
+      scale,
+      denoise_level,
+      alpha: self.alpha.unwrap_or(defaults.alpha),
+      tile_size,
+      lossless: self.lossless,
+    })
+  }
+}
+
+/// Writes a line of the output stream.
+fn emit(line: Value) {
+  let mut stdout = io::stdout().lock();
+  writeln!(stdout, "{line}").expect("Failed to write to stdout");
+  stdout.flush().expect("Failed to write to stdout");
+}
+
+/// Runs the JSON jobs read line by line from stdin until it is closed, one at a time.
+///
+/// Each job gets a `started` line, `progress` lines after each tile when tiled, and then a
+/// `done` or an `error` line on stdout, while the logs go to stderr.
+pub fn work(args: WorkerArgs) -> Result<(), candle_core::Error> {
+  // The id of the running job, for the progress lines
+  let current_id = Arc::new(Mutex::new(Value::Null));
+
+  let progress = {
+    let current_id = current_id.clone();
+    Arc::new(move |progress: Progress| {
+      let id = current_id
+        .lock()
+        .expect("The job id lock is poisoned")
+        .clone();
+      emit(json!({
+        "event": "progress",
+        "id": id,
+        "stage": progress.stage,
+        "stage_num": Progress::STAGE_NUM,
+        "tile": progress.tile,
+        "tile_num": progress.tile_num,
+      }));
+    })
+  };
+
+  let upscaler = Upscaler::new(args.models, Some(progress))?;
+  emit(json!({ "event": "ready" }));
+
+  for (idx, line) in io::stdin().lock().lines().enumerate() {
+    let line = line.expect("Failed to read stdin");
+    if line.trim().is_empty() {
+      continue;
+    }
+
+    let line_id = Value::from(idx + 1);
+
+    let job: Job = match serde_json::from_str(&line) {
+      Ok(job) => job,
+      Err(err) => {
+        emit(json!({
+          "event": "error",
+          "id": line_id,
+          "message": format!("invalid job: {err}"),
+        }));
+        continue;
+      }
+    };
+
+    let id = job.id.clone().unwrap_or(line_id);
+    *current_id.lock().expect("The job id lock is poisoned") = id.clone();
+
+    let _span = tracing::info_span!("job", %id).entered();
+    emit(json!({ "event": "started", "id": id }));
+
+    let start = Instant::now();
+    let res = job
+      .options()
+      .map_err(JobError::Invalid)
+      .and_then(|options| {
+        let output_format = output_format(&job.output, options.lossless)
+          .map_err(|err| JobError::Invalid(err.to_owned()))?;
+        upscaler.upscale(&options, &job.input, &job.output, output_format)
+      });
+
+    match res {
+      Ok((width, height)) => emit(json!({
+        "event": "done",
+        "id": id,
+        "output": job.output,
+        "width": width,
+        "height": height,
+        "seconds": start.elapsed().as_secs_f64(),
+      })),
+      Err(err) => {
+        let message = match err {
+          JobError::Invalid(message) => message,
+          JobError::Failed(err) => err.to_string(),
+        };
+        tracing::error!("{message}");
+        emit(json!({ "event": "error", "id": id, "message": message }));
+      }
+    }
+  }
+
+  Ok(())
+}
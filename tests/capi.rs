@@ -0,0 +1,55 @@
+//! Compiles `capi/test.c` against the C API and runs it with a randomly initialised 2x model.
+
+#![cfg(target_os = "linux")]
+
+use std::{env, ffi::OsString, io::ErrorKind, path::Path, process::Command};
+
+use candle_core::{DType, Device};
+use candle_nn::{VarBuilder, VarMap};
+use real_cugan_rs::model::RealCugan;
+
+#[test]
+fn c_api() {
+  let dir = Path::new(env!("CARGO_TARGET_TMPDIR"));
+  // `cargo test` builds the `cdylib` next to the test binaries
+  let deps = env::current_exe().unwrap().parent().unwrap().to_owned();
+
+  // The variables are randomly initialised as the network asks for them
+  let varmap = VarMap::new();
+  let vb = VarBuilder::from_varmap(&varmap, DType::F32, &Device::Cpu);
+  RealCugan::new(2, 1.0, None, true, false, vb).unwrap();
+  let model = dir.join("capi-up2x.safetensors");
+  varmap.save(&model).unwrap();
+
+  let exe = dir.join("capi-test");
+  let cc = env::var_os("CC").unwrap_or_else(|| OsString::from("cc"));
+  let status = Command::new(&cc)
+    .arg(concat!(env!("CARGO_MANIFEST_DIR"), "/capi/test.c"))
+    .arg(concat!("-I", env!("CARGO_MANIFEST_DIR"), "/capi"))
+    .arg("-L")
+    .arg(&deps)
+    .args(["-lreal_cugan_rs", "-o"])
+    .arg(&exe)
+    .status();
+  let status = match status {
+    Err(err) if err.kind() == ErrorKind::NotFound => {
+      println!("Skipped, no C compiler {cc:?} found, set `CC` to use another one");
+      return;
+    }
+    status => status.unwrap(),
+  };
+  assert!(status.success(), "Failed to compile capi/test.c");
+
+  let output = Command::new(&exe)
+    .arg(&model)
+    .env("LD_LIBRARY_PATH", &deps)
+    .output()
+    .unwrap();
+  let stdout = String::from_utf8_lossy(&output.stdout);
+  assert!(
+    output.status.success(),
+    "{stdout}{}",
+    String::from_utf8_lossy(&output.stderr)
+  );
+  assert!(stdout.contains("all ok"));
+}
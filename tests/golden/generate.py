@@ -0,0 +1,181 @@
+"""Computes the golden references of the cases that upstream Real-CUGAN can run.
+
+Builds the networks of `upcunet_v3.py` from https://github.com/bilibili/ailab (under
+`Real-CUGAN/`) with the deterministic weights of `src/model/golden.rs`, runs them on the same
+inputs in float32 on the CPU and saves the outputs to `tests/golden/upstream.safetensors`:
+
+    python tests/golden/generate.py path/to/upcunet_v3.py
+
+which needs PyTorch. Without it, `--float64` runs the same cases through the plain Python
+transcription of `upcunet_v3.py` in `upcunet_f64.py` instead, in float64:
+
+    python3.12 tests/golden/generate.py --float64
+
+The file records which of both wrote it in its metadata.
+"""
+
+import array
+import importlib.util
+import json
+import math
+import sys
+from pathlib import Path
+
+OUTPUT = Path(__file__).with_name("upstream.safetensors")
+
+# Same as in `golden.rs`
+ALPHA = 0.8
+
+
+def float32(values):
+    """Rounds the values to float32."""
+    return array.array("f", values).tolist()
+
+
+def weights(name, shape):
+    """The pattern of `weights` in `golden.rs`, flattened."""
+    if len(shape) == 1:
+        bound = 0.05
+    else:
+        bound = math.sqrt(6 / math.prod(shape[1:]))
+
+    seed = 0
+    for b in name.encode():
+        seed = (seed * 31 + b) % 65521
+
+    return float32(
+        ((i * 7919 + seed) % 2003 / 2002 * 2 - 1) * bound for i in range(math.prod(shape))
+    )
+
+
+def input(height, width):
+    """The pattern of `input` in `golden.rs`, as `[channel][y][x]` lists."""
+    return [
+        [float32((x * 13 + y * 7 + c * 50) % 64 / 63 for x in range(width)) for y in range(height)]
+        for c in range(3)
+    ]
+
+
+def build(model):
+    import torch
+
+    with torch.no_grad():
+        for name, param in model.named_parameters():
+            param.copy_(torch.tensor(weights(name, tuple(param.shape))).reshape(param.shape))
+    return model.eval()
+
+
+def whole(model, x, scale):
+    """The `tile_mode == 0` branch of `UpCunet2x.forward` and `UpCunet3x.forward` on an input
+    already in the range of the networks, without the final conversion to bytes."""
+    from torch.nn import functional as F
+
+    _, _, h0, w0 = x.shape
+    multiple, pad = {2: (2, 18), 3: (4, 14)}[scale]
+    ph = ((h0 - 1) // multiple + 1) * multiple
+    pw = ((w0 - 1) // multiple + 1) * multiple
+
+    x = F.pad(x, (pad, pad + pw - w0, pad, pad + ph - h0), "reflect")
+    x = model.unet1.forward(x)
+    x0 = model.unet2.forward(x, ALPHA)
+    x = F.pad(x, (-20, -20, -20, -20))
+    x = x0 + x
+    return x[:, :, : h0 * scale, : w0 * scale]
+
+
+def save(tensors, metadata, path):
+    """Writes `(shape, values)` pairs as float32 tensors in the safetensors format, without the
+    `safetensors` package."""
+    header, data, offset = {"__metadata__": metadata}, [], 0
+    for name, (shape, values) in tensors.items():
+        floats = array.array("f", values)
+        if sys.byteorder == "big":
+            floats.byteswap()
+        raw = floats.tobytes()
+        header[name] = {
+            "dtype": "F32",
+            "shape": list(shape),
+            "data_offsets": [offset, offset + len(raw)],
+        }
+        data.append(raw)
+        offset += len(raw)
+
+    encoded = json.dumps(header).encode()
+    encoded += b" " * (-len(encoded) % 8)
+    with open(path, "wb") as f:
+        f.write(len(encoded).to_bytes(8, "little"))
+        f.write(encoded)
+        f.writelines(data)
+
+
+def run_torch(path):
+    """Runs the cases with the PyTorch code of upstream."""
+    import torch
+
+    spec = importlib.util.spec_from_file_location("upcunet_v3", path)
+    upstream = importlib.util.module_from_spec(spec)
+    spec.loader.exec_module(upstream)
+
+    torch.set_grad_enabled(False)
+    tensor = lambda x: torch.tensor(x)[None]
+    outputs = {}
+
+    outputs["unet1"] = build(upstream.UNet1(3, 3, deconv=True))(tensor(input(28, 32)))
+    outputs["unet1_x3"] = build(upstream.UNet1x3(3, 3, deconv=True))(tensor(input(28, 32)))
+    outputs["unet2"] = build(upstream.UNet2(3, 3, deconv=True))(tensor(input(52, 56)), ALPHA)
+
+    # Odd sizes, which are padded before inference and cropped afterwards. PyTorch only
+    # reflects padding narrower than the image.
+    x = tensor(input(21, 23))
+    outputs["up2x_whole"] = whole(build(upstream.UpCunet2x(3, 3)), x, 2)
+    outputs["up3x_whole"] = whole(build(upstream.UpCunet3x(3, 3)), x, 3)
+
+    return {
+        name: (tuple(output.shape), output.flatten().tolist()) for name, output in outputs.items()
+    }
+
+
+def run_float64():
+    """Runs the cases with the transcription in `upcunet_f64.py`."""
+    sys.path.insert(0, str(Path(__file__).parent))
+    import upcunet_f64 as f64
+
+    p = f64.Params(weights)
+    outputs = {}
+
+    outputs["unet1"] = f64.UNet1(p, 3, 3).forward(input(28, 32))
+    outputs["unet1_x3"] = f64.UNet1(p, 3, 3, x3=True).forward(input(28, 32))
+    outputs["unet2"] = f64.UNet2(p, 3, 3).forward(input(52, 56), ALPHA)
+
+    # Odd sizes, as in `run_torch`
+    x = input(21, 23)
+    outputs["up2x_whole"] = f64.whole(p, x, 2, ALPHA)
+    outputs["up3x_whole"] = f64.whole(p, x, 3, ALPHA)
+
+    return {
+        name: (
+            (1, len(output), len(output[0]), len(output[0][0])),
+            [v for c in output for row in c for v in row],
+        )
+        for name, output in outputs.items()
+    }
+
+
+def main():
+    if len(sys.argv) != 2:
+        sys.exit(f"usage: {sys.argv[0]} path/to/upcunet_v3.py | --float64")
+
+    if sys.argv[1] == "--float64":
+        outputs = run_float64()
+        generator = "upcunet_f64.py in float64"
+    else:
+        outputs = run_torch(sys.argv[1])
+        generator = "upcunet_v3.py with PyTorch in float32"
+
+    save(outputs, {"generator": generator}, OUTPUT)
+    for name, (shape, _) in outputs.items():
+        print(f"{name}: {shape}")
+
+
+if __name__ == "__main__":
+    main()
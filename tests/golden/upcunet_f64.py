@@ -0,0 +1,225 @@
+"""A float64 transcription of the networks of upstream `upcunet_v3.py`, in plain Python.
+
+Each module follows the `forward` of its PyTorch counterpart line by line, so that `generate.py
+--float64` can compute the references where PyTorch is not installed. Tensors are lists of
+channels, each a list of rows, without the batch dimension. Needs Python 3.12 for `math.sumprod`.
+"""
+
+import math
+
+
+def conv2d(x, weight, bias, kernel, stride=1):
+    """`nn.Conv2d` without padding, `weight` being a function of `(o, i, ky, kx)`."""
+    cin, h, w = len(x), len(x[0]), len(x[0][0])
+    cout = len(bias)
+    oh, ow = (h - kernel) // stride + 1, (w - kernel) // stride + 1
+
+    # The weights of each output channel, flattened in the order of the patches below
+    kernels = [
+        [weight(o, i, ky, kx) for i in range(cin) for ky in range(kernel) for kx in range(kernel)]
+        for o in range(cout)
+    ]
+
+    out = [[[0.0] * ow for _ in range(oh)] for _ in range(cout)]
+    for y in range(oh):
+        for xx in range(ow):
+            x0 = xx * stride
+            patch = [
+                v
+                for c in x
+                for ky in range(kernel)
+                for v in c[y * stride + ky][x0 : x0 + kernel]
+            ]
+            for o in range(cout):
+                out[o][y][xx] = math.sumprod(kernels[o], patch) + bias[o]
+    return out
+
+
+def conv_transpose2d(x, weight, bias, kernel, stride, padding):
+    """`nn.ConvTranspose2d`, `weight` being a function of `(i, o, ky, kx)`."""
+    cin, h, w = len(x), len(x[0]), len(x[0][0])
+    cout = len(bias)
+    oh = (h - 1) * stride - 2 * padding + kernel
+    ow = (w - 1) * stride - 2 * padding + kernel
+
+    columns = {
+        (o, ky, kx): [weight(i, o, ky, kx) for i in range(cin)]
+        for o in range(cout)
+        for ky in range(kernel)
+        for kx in range(kernel)
+    }
+
+    out = [[[bias[o]] * ow for _ in range(oh)] for o in range(cout)]
+    for iy in range(h):
+        for ix in range(w):
+            v = [c[iy][ix] for c in x]
+            for ky in range(kernel):
+                y = iy * stride - padding + ky
+                if not 0 <= y < oh:
+                    continue
+                for kx in range(kernel):
+                    xx = ix * stride - padding + kx
+                    if not 0 <= xx < ow:
+                        continue
+                    for o in range(cout):
+                        out[o][y][xx] += math.sumprod(v, columns[o, ky, kx])
+    return out
+
+
+def leaky_relu(x, slope=0.1):
+    return [[[v if v >= 0 else v * slope for v in row] for row in c] for c in x]
+
+
+def add(a, b):
+    return [[[p + q for p, q in zip(ra, rb)] for ra, rb in zip(ca, cb)] for ca, cb in zip(a, b)]
+
+
+def scale(x, factor):
+    return [[[v * factor for v in row] for row in c] for c in x]
+
+
+def crop(x, n):
+    """`F.pad(x, (-n, -n, -n, -n))`."""
+    return [[row[n:-n] for row in c[n:-n]] for c in x]
+
+
+def reflect_pad(x, left, right, top, bottom):
+    """`F.pad(x, (left, right, top, bottom), "reflect")`, narrower than the image."""
+
+    def pad(seq, before, after):
+        # Like PyTorch, which refuses wider padding
+        assert max(before, after) < len(seq), "the padding must be narrower than the input"
+        return seq[before:0:-1] + seq + seq[-2 : -2 - after : -1]
+
+    return [pad([pad(row, left, right) for row in c], top, bottom) for c in x]
+
+
+class Params:
+    """The variables of a network, created by name like `named_parameters` lists them."""
+
+    def __init__(self, weights, prefix=""):
+        self.weights = weights
+        self.prefix = prefix
+
+    def pp(self, name):
+        return Params(self.weights, f"{self.prefix}{name}.")
+
+    def conv(self, name, cin, cout, kernel):
+        """The weight function and the bias of `nn.Conv2d`."""
+        w = self.weights(f"{self.prefix}{name}.weight", (cout, cin, kernel, kernel))
+        b = self.weights(f"{self.prefix}{name}.bias", (cout,))
+        return (lambda o, i, ky, kx: w[((o * cin + i) * kernel + ky) * kernel + kx]), b
+
+    def conv_transpose(self, name, cin, cout, kernel):
+        """The weight function and the bias of `nn.ConvTranspose2d`."""
+        w = self.weights(f"{self.prefix}{name}.weight", (cin, cout, kernel, kernel))
+        b = self.weights(f"{self.prefix}{name}.bias", (cout,))
+        return (lambda i, o, ky, kx: w[((i * cout + o) * kernel + ky) * kernel + kx]), b
+
+
+class SEBlock:
+    def __init__(self, p, channels, reduction=8):
+        self.conv1 = p.conv("conv1", channels, channels // reduction, 1)
+        self.conv2 = p.conv("conv2", channels // reduction, channels, 1)
+
+    def forward(self, x):
+        x0 = [[[math.fsum(v for row in c for v in row) / (len(c) * len(c[0]))]] for c in x]
+        x0 = conv2d(x0, *self.conv1, 1)
+        x0 = [[[max(c[0][0], 0.0)]] for c in x0]
+        x0 = conv2d(x0, *self.conv2, 1)
+        x0 = [1 / (1 + math.exp(-c[0][0])) for c in x0]
+        return [[[v * s for v in row] for row in c] for c, s in zip(x, x0)]
+
+
+class UNetConv:
+    def __init__(self, p, cin, mid, cout, se):
+        self.conv0 = p.conv("conv.0", cin, mid, 3)
+        self.conv2 = p.conv("conv.2", mid, cout, 3)
+        self.seblock = SEBlock(p.pp("seblock"), cout) if se else None
+
+    def forward(self, x):
+        z = leaky_relu(conv2d(x, *self.conv0, 3))
+        z = leaky_relu(conv2d(z, *self.conv2, 3))
+        if self.seblock is not None:
+            z = self.seblock.forward(z)
+        return z
+
+
+class UNet1:
+    def __init__(self, p, cin, cout, x3=False):
+        self.conv1 = UNetConv(p.pp("conv1"), cin, 32, 64, se=False)
+        self.conv1_down = p.conv("conv1_down", 64, 64, 2)
+        self.conv2 = UNetConv(p.pp("conv2"), 64, 128, 64, se=True)
+        self.conv2_up = p.conv_transpose("conv2_up", 64, 64, 2)
+        self.conv3 = p.conv("conv3", 64, 64, 3)
+        # `UNet1x3` only differs by its last layer
+        self.bottom = (5, 3, 2) if x3 else (4, 2, 3)
+        self.conv_bottom = p.conv_transpose("conv_bottom", 64, cout, self.bottom[0])
+
+    def forward(self, x):
+        x1 = self.conv1.forward(x)
+        x2 = conv2d(x1, *self.conv1_down, 2, 2)
+        x2 = leaky_relu(x2)
+        x2 = self.conv2.forward(x2)
+        x2 = conv_transpose2d(x2, *self.conv2_up, 2, 2, 0)
+        x2 = leaky_relu(x2)
+        x1 = crop(x1, 4)
+        x3 = conv2d(add(x1, x2), *self.conv3, 3)
+        x3 = leaky_relu(x3)
+        return conv_transpose2d(x3, *self.conv_bottom, *self.bottom)
+
+
+class UNet2:
+    def __init__(self, p, cin, cout, deconv=True):
+        self.conv1 = UNetConv(p.pp("conv1"), cin, 32, 64, se=False)
+        self.conv1_down = p.conv("conv1_down", 64, 64, 2)
+        self.conv2 = UNetConv(p.pp("conv2"), 64, 64, 128, se=True)
+        self.conv2_down = p.conv("conv2_down", 128, 128, 2)
+        self.conv3 = UNetConv(p.pp("conv3"), 128, 256, 128, se=True)
+        self.conv3_up = p.conv_transpose("conv3_up", 128, 128, 2)
+        self.conv4 = UNetConv(p.pp("conv4"), 128, 64, 64, se=True)
+        self.conv4_up = p.conv_transpose("conv4_up", 64, 64, 2)
+        self.conv5 = p.conv("conv5", 64, 64, 3)
+        self.deconv = deconv
+        if deconv:
+            self.conv_bottom = p.conv_transpose("conv_bottom", 64, cout, 4)
+        else:
+            self.conv_bottom = p.conv("conv_bottom", 64, cout, 3)
+
+    def forward(self, x, alpha=1):
+        x1 = self.conv1.forward(x)
+        x2 = conv2d(x1, *self.conv1_down, 2, 2)
+        x2 = leaky_relu(x2)
+        x2 = self.conv2.forward(x2)
+        x3 = conv2d(x2, *self.conv2_down, 2, 2)
+        x3 = leaky_relu(x3)
+        x3 = self.conv3.forward(x3)
+        x3 = conv_transpose2d(x3, *self.conv3_up, 2, 2, 0)
+        x3 = leaky_relu(x3)
+        x2 = crop(x2, 4)
+        x4 = self.conv4.forward(add(x2, x3))
+        x4 = scale(x4, alpha)
+        x4 = conv_transpose2d(x4, *self.conv4_up, 2, 2, 0)
+        x4 = leaky_relu(x4)
+        x1 = crop(x1, 16)
+        x5 = conv2d(add(x1, x4), *self.conv5, 3)
+        x5 = leaky_relu(x5)
+        if self.deconv:
+            return conv_transpose2d(x5, *self.conv_bottom, 4, 2, 3)
+        return conv2d(x5, *self.conv_bottom, 3)
+
+
+def whole(p, x, upscale, alpha):
+    """The `tile_mode == 0` branch of `UpCunet2x.forward` and `UpCunet3x.forward`, like `whole`
+    in `generate.py`."""
+    h0, w0 = len(x[0]), len(x[0][0])
+    multiple, pad = {2: (2, 18), 3: (4, 14)}[upscale]
+    ph = ((h0 - 1) // multiple + 1) * multiple
+    pw = ((w0 - 1) // multiple + 1) * multiple
+
+    x = reflect_pad(x, pad, pad + pw - w0, pad, pad + ph - h0)
+    x = UNet1(p.pp("unet1"), 3, 3, x3=upscale == 3).forward(x)
+    x0 = UNet2(p.pp("unet2"), 3, 3, deconv=False).forward(x, alpha)
+    x = crop(x, 20)
+    x = add(x0, x)
+    return [[row[: w0 * upscale] for row in c[: h0 * upscale]] for c in x]